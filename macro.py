@@ -1,44 +1,34 @@
-from hid_keycode import Keycode
+import itertools
 import serial
 import time
 
-def presskey(ser, keycode, modifier=None):
-    if modifier != None:
-        ser.write('kd {}\r'.format(modifier).encode('ascii'))
-        ser.flush()
-        time.sleep(0.2)
-    ser.write('kd {}\r'.format(keycode).encode('ascii'))
-    ser.flush()
-    time.sleep(0.1)
-    ser.write('ku {}\r'.format(keycode).encode('ascii'))
+seq_counter = itertools.count()
+
+def command(ser, line):
+    seq = next(seq_counter) % 65536
+    ser.write('#{} {}\r'.format(seq, line).encode('ascii'))
     ser.flush()
-    time.sleep(0.1)
+    # Skip log lines until the acknowledgement for this command arrives
+    while True:
+        reply = ser.readline().decode('utf-8', 'replace').split()
+        if len(reply) >= 2 and reply[0] in ('OK', 'ERR') and reply[1] == str(seq):
+            if reply[0] == 'ERR':
+                raise RuntimeError('{!r} failed: {}'.format(line, ' '.join(reply[2:])))
+            return
+
+def presskey(ser, keycode, modifier=None):
     if modifier != None:
-        ser.write('ku {}\r'.format(modifier).encode('ascii'))
-        ser.flush()
-        time.sleep(0.1)
+        keycode = '{}+{}'.format(modifier, keycode)
+    command(ser, 'kp {}'.format(keycode))
 
 with serial.Serial('/dev/ttyACM0', 115200) as ser:
-    presskey(ser, Keycode.SPACE, Keycode.ALT)
-    presskey(ser, Keycode.K)
-    presskey(ser, Keycode.W)
-    presskey(ser, Keycode.R)
-    presskey(ser, Keycode.I)
-    presskey(ser, Keycode.T)
-    presskey(ser, Keycode.E)
-    presskey(ser, Keycode.ENTER)
+    presskey(ser, 'SPACEBAR', 'LEFT_ALT')
+    presskey(ser, 'K')
+    presskey(ser, 'W')
+    presskey(ser, 'R')
+    presskey(ser, 'I')
+    presskey(ser, 'T')
+    presskey(ser, 'E')
+    presskey(ser, 'ENTER')
     time.sleep(5)
-    presskey(ser, Keycode.H, Keycode.SHIFT)
-    presskey(ser, Keycode.E)
-    presskey(ser, Keycode.L)
-    presskey(ser, Keycode.L)
-    presskey(ser, Keycode.O)
-    presskey(ser, Keycode.COMMA)
-    presskey(ser, Keycode.SPACE)
-    presskey(ser, Keycode.W)
-    presskey(ser, Keycode.O)
-    presskey(ser, Keycode.R)
-    presskey(ser, Keycode.L)
-    presskey(ser, Keycode.D)
-    presskey(ser, Keycode.PERIOD)
-
+    command(ser, 'type Hello, world.')
@@ -1,7 +1,9 @@
 MEMORY
 {
   /* NOTE 1 K = 1 KiBi = 1024 bytes */
-  FLASH : ORIGIN = 0x08000000, LENGTH = 1M
+  FLASH : ORIGIN = 0x08000000, LENGTH = 1M - 4K
+  /* Last two pages of bank 2, holding the config (see src/config.rs) */
+  CONFIG : ORIGIN = 0x080FF000, LENGTH = 4K
   RAM : ORIGIN = 0x20000000, LENGTH = 96K
 }
 
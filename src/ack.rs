@@ -0,0 +1,137 @@
+//! Receiver state sent back to presenters in ACK payloads
+//!
+//! Every packet a presenter sends is answered by the ACK carrying an
+//! `Opcode::Status` frame, with the sequence number of the last frame
+//! accepted from that pipe in the header:
+//!
+//! | Offset | Size | Field                                       |
+//! |--------|------|---------------------------------------------|
+//! | 0      | 1    | Version, `FRAME_VERSION`                    |
+//! | 1      | 1    | `Opcode::Status`                            |
+//! | 2      | 1    | Last accepted sequence number               |
+//! | 3      | 1    | Flags, see `StatusFlags`                    |
+//! | 4      | 1    | Keyboard LEDs, as in the HID output report  |
+//! | 5      | 2    | Slide timer in seconds, little endian       |
+//! | 7      | 1    | Channel to move to, with `StatusFlags::HOP` |
+//! | 8      | 4    | Lowest counter accepted next, little endian |
+//!
+//! A presenter whose counter is below the lowest one accepted moves it up,
+//! which is how it catches up with the counter floor after a reset of the
+//! receiver, see `secure`.
+//! A payload is queued after a packet arrives, so it goes out with the ACK
+//! of the next packet and is as old as the time between the two.
+//!
+//! Status frames are not secured. Anyone in range can read the USB state,
+//! the LEDs and the timer from them, and can forge an ACK feeding a
+//! presenter false ones. A forged `StatusFlags::HOP` can move a presenter
+//! away, which loses the link as jamming would, and a forged counter can
+//! make it skip counters. The receiver only moves to channels it announced
+//! itself, so a forged ACK can't move the receiver.
+
+use crate::link::PIPE_COUNT;
+use crate::nrf24_mode::{reg, NRF24Device, NRF24Mode};
+use crate::packet::{Opcode, FRAME_VERSION};
+use embedded_nrf24l01::Configuration;
+
+const STATUS_LEN: usize = 12;
+
+bitflags! {
+    pub struct StatusFlags: u8 {
+        /// The host has configured the USB device
+        const USB_CONFIGURED = 0x01;
+        const USB_SUSPENDED = 0x02;
+        /// The sequence number is valid, i.e. a frame was accepted
+        const SEQ_VALID = 0x04;
+        const TIMER_RUNNING = 0x08;
+        /// The receiver is about to move to another channel, see `hopping`.
+        /// Like the rest of the frame it may be forged.
+        const HOP = 0x10;
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Status {
+    pub flags: StatusFlags,
+    pub keyboard_leds: u8,
+    pub timer_seconds: u32,
+    pub hop_channel: Option<u8>,
+}
+
+impl Status {
+    fn encode(&self, last_seq: Option<u8>, next_counter: u32) -> [u8; STATUS_LEN] {
+        let mut flags = self.flags;
+        flags.set(StatusFlags::SEQ_VALID, last_seq.is_some());
+        flags.set(StatusFlags::HOP, self.hop_channel.is_some());
+        let timer = self.timer_seconds.min(u32::from(u16::MAX)) as u16;
+
+        let mut frame = [0u8; STATUS_LEN];
+        frame[0] = FRAME_VERSION;
+        frame[1] = Opcode::Status as u8;
+        frame[2] = last_seq.unwrap_or(0);
+        frame[3] = flags.bits();
+        frame[4] = self.keyboard_leds;
+        frame[5..7].copy_from_slice(&timer.to_le_bytes());
+        frame[7] = self.hop_channel.unwrap_or(0);
+        frame[8..12].copy_from_slice(&next_counter.to_le_bytes());
+        frame
+    }
+}
+
+/// Keeps one status payload queued for each pipe
+#[derive(Debug)]
+pub struct AckPayloads {
+    queued: [bool; PIPE_COUNT],
+}
+
+impl AckPayloads {
+    pub const fn new() -> Self {
+        Self {
+            queued: [false; PIPE_COUNT],
+        }
+    }
+
+    /// Turns on ACK payloads, next to the dynamic payload length set up by
+    /// `set_pipes_rx_lengths`
+    pub fn enable(nrf24l01: &mut NRF24Mode<NRF24Device>) {
+        let feature = nrf24l01.read_register(reg::FEATURE);
+        nrf24l01.write_register(
+            reg::FEATURE,
+            feature | reg::FEATURE_EN_DPL | reg::FEATURE_EN_ACK_PAY,
+        );
+    }
+
+    /// Notes that the TX FIFO was flushed, so no payload is queued
+    pub fn clear(&mut self) {
+        self.queued = [false; PIPE_COUNT];
+    }
+
+    /// Notes that the ACK of a packet on the pipe took its payload
+    pub fn sent(&mut self, pipe: u8) {
+        if let Some(queued) = self.queued.get_mut(usize::from(pipe)) {
+            *queued = false;
+        }
+    }
+
+    /// Queues the status for a pipe which has none queued
+    pub fn refill(
+        &mut self,
+        nrf24l01: &mut NRF24Mode<NRF24Device>,
+        pipe: u8,
+        status: &Status,
+        last_seq: Option<u8>,
+        next_counter: u32,
+    ) {
+        let index = usize::from(pipe);
+        if self.queued.get(index) != Some(&false) {
+            return;
+        }
+
+        if nrf24l01.read_register(reg::FIFO_STATUS) & reg::FIFO_TX_FULL != 0 {
+            // Payloads of pipes which went quiet fill the FIFO up
+            nrf24l01.configuration_mut().flush_tx().ok();
+            self.clear();
+        }
+        nrf24l01.write_ack_payload(pipe, &status.encode(last_seq, next_counter));
+        self.queued[index] = true;
+    }
+}
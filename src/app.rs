@@ -1,64 +1,404 @@
-use crate::command::Commands;
+use crate::clock::{self, Instant};
+use crate::command::{
+    Chord, Commands, HelpTopic, Text, TimerAction, CONSUMER_NAMES, DEFAULT_HOLD_MS, KEY_NAMES,
+    SYSTEM_NAMES,
+};
+use crate::config::{Config, ConfigCommand, ConfigValue};
+use crate::flash::FlashError;
 use crate::hid_report::*;
-use crate::{USB_HID_CURSOR, USB_HID_KBD, USB_HID_MOUSE};
-use cortex_m::interrupt::free;
+use crate::keyboard_leds;
+use crate::layout::{KeyboardLayout, LayoutId};
+use crate::link::{SeqTracker, LINK_STATS, PIPE_COUNT};
+use crate::nrf24_mode::{NRF24Device, NRF24Mode};
+use crate::radio::{self, Registers};
+use crate::secure::SecureLink;
+use crate::serial::BlockingSerialWriter;
+use crate::slide_timer::SlideTimer;
+use crate::timer_wheel::TimerWheel;
+use crate::usb::HidClasses;
+use crate::usb_power;
+use core::fmt::{self, Write};
+use rtic::Mutex;
+use usb_device::UsbError;
 
+/// How long a pipe keeps the floor after its last command, unless it holds
+/// something down
+const FLOOR_IDLE_MS: u32 = 5000;
+/// Consumer usages which step a level, so they repeat while held down:
+/// brightness and volume. Keyboard keys are not repeated here, the host
+/// repeats a key as long as its report holds it down.
+const REPEAT_USAGES: &[u16] = &[0x6F, 0x70, 0xE9, 0xEA];
+/// Delay before a held consumer usage repeats
+const REPEAT_DELAY_MS: u32 = 500;
+const REPEAT_INTERVAL_MS: u32 = 100;
+/// Delay before sending a timer's report again when the host had no room
+const RETRY_MS: u32 = 1;
+const TIMER_COUNT: usize = 16;
+
+/// Reasons for a parsed command failing to execute
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExecError {
+    Usb(UsbError),
+    QueueFull,
+    Busy,
+    /// Characters skipped by `type`, with the first one and its byte offset
+    Untypeable {
+        count: u8,
+        first: char,
+        pos: u8,
+    },
+    Flash(FlashError),
+    /// The nRF24 didn't take its settings
+    Radio,
+    /// Floor control is on and another pipe has the floor
+    NoFloor(u8),
+    /// A presenter sent a command which only the host may run
+    NotWireless,
+}
+
+impl ExecError {
+    /// Numeric code reported to the host, following the `ParseError` codes
+    pub const fn code(&self) -> u8 {
+        match self {
+            ExecError::Usb(_) => 32,
+            ExecError::QueueFull => 33,
+            ExecError::Busy => 34,
+            ExecError::Untypeable { .. } => 35,
+            ExecError::Flash(_) => 36,
+            ExecError::Radio => 37,
+            ExecError::NoFloor(_) => 38,
+            ExecError::NotWireless => 39,
+        }
+    }
+}
+
+impl fmt::Display for ExecError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExecError::Usb(e) => write!(f, "report not sent: {:?}", e),
+            ExecError::QueueFull => f.write_str("command queue full"),
+            ExecError::Busy => f.write_str("keyboard busy"),
+            ExecError::Untypeable { count, first, pos } => write!(
+                f,
+                "{} characters not typed, first {:?} at {}",
+                count, first, pos
+            ),
+            ExecError::Flash(e) => write!(f, "config not saved: {:?}", e),
+            ExecError::Radio => f.write_str("radio not responding"),
+            ExecError::NoFloor(pipe) => write!(f, "pipe {} has the floor", pipe),
+            ExecError::NotWireless => f.write_str("command not allowed over the air"),
+        }
+    }
+}
+
+impl From<UsbError> for ExecError {
+    fn from(e: UsbError) -> Self {
+        ExecError::Usb(e)
+    }
+}
+
+impl From<FlashError> for ExecError {
+    fn from(e: FlashError) -> Self {
+        ExecError::Flash(e)
+    }
+}
+
+/// Actions which `App` runs later, from `App::poll`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Timer {
+    /// End of the hold of the running tap
+    TapRelease,
+    /// Next report of a held consumer usage repeating, pressing it again or
+    /// releasing it in between
+    Repeat {
+        source: Source,
+        usage: u16,
+        pressed: bool,
+    },
+    /// Sends what all sources hold down again, after a presenter went silent
+    Resend(ReportKind),
+}
+
+/// HID reports carrying what is held down
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum ReportKind {
+    Mouse,
+    Keyboard,
+    Consumer,
+    System,
+}
+
+impl ReportKind {
+    const ALL: [ReportKind; 4] = [
+        ReportKind::Mouse,
+        ReportKind::Keyboard,
+        ReportKind::Consumer,
+        ReportKind::System,
+    ];
+}
+
+type Timers = TimerWheel<Timer, TIMER_COUNT>;
+
+/// Commands which keep running across several `App::poll` calls
 #[derive(Debug)]
-pub struct App {
-    mouse_pressed: MouseButtons,
-    keys_pressed: [u8; 6],
-    modifiers: KeyboardModifiers,
+enum Job {
+    Typing(TypingJob),
+    Tap(TapJob),
 }
 
-impl App {
-    pub fn new() -> Self {
+/// Progress of a `type` command
+#[derive(Debug)]
+struct TypingJob {
+    text: Text,
+    /// Byte offset of the next character
+    pos: usize,
+    /// Whether the current character's key is held down
+    pressed: bool,
+    /// Characters which the layout cannot type
+    skipped: Option<ExecError>,
+}
+
+impl TypingJob {
+    fn new(text: Text) -> Self {
         Self {
-            mouse_pressed: MouseButtons::empty(),
-            keys_pressed: [0u8; 6],
-            modifiers: KeyboardModifiers::empty(),
+            text,
+            pos: 0,
+            pressed: false,
+            skipped: None,
         }
     }
 
-    pub fn process_cmd(&mut self, cmd: Commands) {
-        match cmd {
-            Commands::MouseDown(btn) => {
-                self.mouse_pressed |= btn;
-                send_mouse_report(0, 0, self.mouse_pressed);
-            }
-            Commands::MouseUp(btn) => {
-                self.mouse_pressed -= btn;
-                send_mouse_report(0, 0, self.mouse_pressed);
-            }
-            Commands::KeyDown(key) => {
-                if KeyboardModifiers::is_modifier(key) {
-                    self.modifiers |= KeyboardModifiers::from_keycode(key);
-                } else {
-                    self.add_key_pressed(key);
-                }
-                send_kbd_report(self.modifiers, &self.keys_pressed);
-            }
-            Commands::KeyUp(key) => {
-                if KeyboardModifiers::is_modifier(key) {
-                    self.modifiers -= KeyboardModifiers::from_keycode(key);
+    /// Sends the next report, returning whether the whole text is typed
+    fn step(
+        &mut self,
+        hid: &mut impl Mutex<T = HidClasses>,
+        layout: &dyn KeyboardLayout,
+        modifiers: KeyboardModifiers,
+        keys: &[u8; 6],
+    ) -> Result<bool, ExecError> {
+        if self.pressed {
+            // Release the character by going back to the keys held before
+            hid.lock(|hid| hid.send_kbd_report(modifiers, keys))?;
+            self.pressed = false;
+            return Ok(false);
+        }
+
+        match self.text.as_str()[self.pos..].chars().next() {
+            Some(c) => {
+                if let Some((modifiers, key)) = layout.char_to_key(c) {
+                    hid.lock(|hid| hid.send_kbd_report(modifiers, &[key, 0, 0, 0, 0, 0]))?;
+                    self.pressed = true;
+                    self.pos += c.len_utf8();
                 } else {
-                    self.remove_key_pressed(key);
+                    warn!("Cannot type {:?} with {} layout", c, layout.name());
+                    self.skip(c);
                 }
-                send_kbd_report(self.modifiers, &self.keys_pressed);
+                Ok(false)
             }
-            Commands::AbsMove(x, y) => {
-                send_cursor_report(x, y);
+            None => self.skipped.map_or(Ok(true), Err),
+        }
+    }
+
+    fn skip(&mut self, c: char) {
+        match &mut self.skipped {
+            Some(ExecError::Untypeable { count, .. }) => *count = count.saturating_add(1),
+            _ => {
+                self.skipped = Some(ExecError::Untypeable {
+                    count: 1,
+                    first: c,
+                    pos: self.pos as u8,
+                })
             }
-            Commands::RelMove(x, y) => {
-                send_mouse_report(x, y, self.mouse_pressed);
+        }
+        self.pos += c.len_utf8();
+    }
+}
+
+/// What a tap presses
+#[derive(Debug)]
+enum Tap {
+    Chord(Chord),
+    Consumer(u16),
+    System(u8),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum TapState {
+    Press,
+    /// Waiting for `Timer::TapRelease`
+    Hold,
+    Release,
+}
+
+/// Progress of a `kp`, `cc ... tap` or `sc ... tap` command
+#[derive(Debug)]
+struct TapJob {
+    tap: Tap,
+    state: TapState,
+}
+
+impl TapJob {
+    fn new(tap: Tap) -> Self {
+        Self {
+            tap,
+            state: TapState::Press,
+        }
+    }
+
+    fn hold_ms(&self) -> u16 {
+        match &self.tap {
+            Tap::Chord(chord) => chord.hold_ms,
+            Tap::Consumer(_) | Tap::System(_) => DEFAULT_HOLD_MS,
+        }
+    }
+
+    /// Sends the press and release reports, returning whether both are sent
+    ///
+    /// The release goes back to what `held` says is held down.
+    fn step(
+        &mut self,
+        hid: &mut impl Mutex<T = HidClasses>,
+        held: &Held,
+        timers: &mut Timers,
+    ) -> Result<bool, ExecError> {
+        match self.state {
+            TapState::Press => {
+                match &self.tap {
+                    Tap::Chord(chord) => hid.lock(|hid| {
+                        hid.send_kbd_report(held.modifiers | chord.modifiers, &chord.keys)
+                    })?,
+                    Tap::Consumer(usage) => hid.lock(|hid| hid.send_consumer_report(*usage))?,
+                    Tap::System(usage) => hid.lock(|hid| hid.send_system_report(*usage))?,
+                }
+                let hold_ms = u32::from(self.hold_ms());
+                self.state = match timers.schedule(clock::millis(), hold_ms, Timer::TapRelease) {
+                    Ok(()) => TapState::Hold,
+                    Err(_) => {
+                        warn!("No timer left, releasing the tap now");
+                        TapState::Release
+                    }
+                };
+                Ok(false)
             }
-            Commands::Wheel(w) => {
-                send_wheel_report(w, self.mouse_pressed);
+            TapState::Hold => Ok(false),
+            TapState::Release => {
+                match &self.tap {
+                    Tap::Chord(_) => {
+                        hid.lock(|hid| hid.send_kbd_report(held.modifiers, &held.keys))?
+                    }
+                    Tap::Consumer(_) => hid.lock(|hid| hid.send_consumer_report(held.consumer))?,
+                    Tap::System(_) => hid.lock(|hid| hid.send_system_report(held.system))?,
+                }
+                Ok(true)
             }
         }
     }
+}
+
+/// Where a command comes from
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Source {
+    Serial,
+    /// A presenter on an nRF24 pipe
+    Pipe(u8),
+}
+
+impl Source {
+    const COUNT: usize = 1 + PIPE_COUNT;
+
+    fn index(self) -> usize {
+        match self {
+            Source::Serial => 0,
+            Source::Pipe(pipe) => 1 + usize::from(pipe),
+        }
+    }
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Source::Serial => f.write_str("serial"),
+            Source::Pipe(pipe) => write!(f, "pipe {}", pipe),
+        }
+    }
+}
+
+/// Buttons and keys held down by one source
+#[derive(Clone, Copy, Debug)]
+struct Held {
+    mouse: MouseButtons,
+    keys: [u8; 6],
+    modifiers: KeyboardModifiers,
+    /// Consumer Page usage held down by `cc ... down`, 0 if none
+    consumer: u16,
+    /// System Control usage held down by `sc ... down`, 0 if none
+    system: u8,
+}
+
+impl Held {
+    fn new() -> Self {
+        Self {
+            mouse: MouseButtons::empty(),
+            keys: [0u8; 6],
+            modifiers: KeyboardModifiers::empty(),
+            consumer: 0,
+            system: 0,
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.mouse.is_empty()
+            && self.keys[0] == 0x00
+            && self.modifiers.is_empty()
+            && self.consumer == 0
+            && self.system == 0
+    }
+
+    /// Whether the report of `kind` carries something held down
+    fn holds(&self, kind: ReportKind) -> bool {
+        match kind {
+            ReportKind::Mouse => !self.mouse.is_empty(),
+            ReportKind::Keyboard => self.keys[0] != 0x00 || !self.modifiers.is_empty(),
+            ReportKind::Consumer => self.consumer != 0,
+            ReportKind::System => self.system != 0,
+        }
+    }
+
+    /// Adds what `other` holds down. Keys beyond the sixth are dropped, and
+    /// the consumer and system usages of `self` win.
+    fn merge(&mut self, other: &Held) {
+        self.mouse |= other.mouse;
+        self.modifiers |= other.modifiers;
+        for &key in other.keys.iter().take_while(|&&key| key != 0x00) {
+            self.add_key(key);
+        }
+        if self.consumer == 0 {
+            self.consumer = other.consumer;
+        }
+        if self.system == 0 {
+            self.system = other.system;
+        }
+    }
+
+    /// Holds `key` down, as a modifier or in a key slot
+    fn press(&mut self, key: u8) {
+        if KeyboardModifiers::is_modifier(key) {
+            self.modifiers |= KeyboardModifiers::from_keycode(key);
+        } else {
+            self.add_key(key);
+        }
+    }
 
-    fn add_key_pressed(&mut self, key: u8) {
-        for slot in &mut self.keys_pressed {
+    fn release(&mut self, key: u8) {
+        if KeyboardModifiers::is_modifier(key) {
+            self.modifiers -= KeyboardModifiers::from_keycode(key);
+        } else {
+            self.remove_key(key);
+        }
+    }
+
+    fn add_key(&mut self, key: u8) {
+        for slot in &mut self.keys {
             if key == *slot {
                 // Duplicate key
                 return;
@@ -70,9 +410,9 @@ impl App {
         }
     }
 
-    fn remove_key_pressed(&mut self, key: u8) {
+    fn remove_key(&mut self, key: u8) {
         let mut idx = None;
-        for (i, slot) in self.keys_pressed.iter().enumerate() {
+        for (i, slot) in self.keys.iter().enumerate() {
             if key == *slot {
                 // Key found
                 idx = Some(i);
@@ -85,82 +425,601 @@ impl App {
         }
         if let Some(idx) = idx {
             if idx < 5 {
-                self.keys_pressed.copy_within(idx + 1.., idx);
+                self.keys.copy_within(idx + 1.., idx);
             }
-            self.keys_pressed[5] = 0x00;
+            self.keys[5] = 0x00;
         }
     }
 }
 
-fn send_cursor_report(x: u16, y: u16) {
-    free(|cs| {
-        let mut usb_hid_cursor_ref = USB_HID_CURSOR.borrow(cs).borrow_mut();
-        let usb_hid_cursor = usb_hid_cursor_ref.as_mut().unwrap();
+/// What commands work on besides the `App`, lent by the task running them
+pub struct Shared<'a, H, R> {
+    pub hid: H,
+    pub radio: R,
+    pub config: &'a mut Config,
+    pub secure_link: &'a mut SecureLink,
+    pub seq_tracker: &'a mut SeqTracker,
+}
+
+#[derive(Debug)]
+pub struct App {
+    /// What each source holds down, by `Source::index`. The host sees all
+    /// of it together, so one source releasing a key doesn't release the
+    /// same key held by another.
+    held: [Held; Source::COUNT],
+    layout: LayoutId,
+    /// Only the pipe having the floor may send commands
+    floor_control: bool,
+    /// Pipe having the floor, and when it sent its last command
+    floor: Option<(u8, Instant)>,
+    slide_timer: SlideTimer,
+    job: Option<Job>,
+    timers: Timers,
+    /// Silence after which a presenter's buttons and keys are released, 0
+    /// for never
+    release_ms: u16,
+    /// When the presenter on each pipe was last heard from
+    last_heard: [Instant; PIPE_COUNT],
+}
 
-        let report = CursorReport::with_position(x, y);
+impl App {
+    pub fn new(config: &Config) -> Self {
+        Self {
+            held: [Held::new(); Source::COUNT],
+            layout: config.layout,
+            floor_control: config.floor_control,
+            floor: None,
+            slide_timer: SlideTimer::new(),
+            job: None,
+            timers: TimerWheel::new(),
+            release_ms: config.release_ms,
+            last_heard: [Instant::now(); PIPE_COUNT],
+        }
+    }
 
-        debug!("Send report: {:?}", &report);
+    /// Lock LEDs as last set by the host
+    pub fn keyboard_leds(&self) -> KeyboardLeds {
+        keyboard_leds::get()
+    }
 
-        if let Err(e) = usb_hid_cursor.push_input(&report) {
-            error!("Cursor Report Error: {:?}", e);
+    pub fn slide_timer(&self) -> &SlideTimer {
+        &self.slide_timer
+    }
+
+    /// What all sources hold down together
+    fn held(&self) -> Held {
+        let mut all = Held::new();
+        for held in &self.held {
+            all.merge(held);
         }
-    });
-}
+        all
+    }
+
+    /// Keeps what the presenter on `pipe` holds down from being released as
+    /// silent
+    ///
+    /// Commands count already. Presenters holding something down keep
+    /// sending packets, see `packet::KEEP_ALIVE_MS`.
+    pub fn heard(&mut self, pipe: u8) {
+        if let Some(last_heard) = self.last_heard.get_mut(usize::from(pipe)) {
+            *last_heard = Instant::now();
+        }
+    }
 
-fn send_mouse_report(x: i16, y: i16, btn: MouseButtons) {
-    free(|cs| {
-        let mut usb_hid_mouse_ref = USB_HID_MOUSE.borrow(cs).borrow_mut();
-        let usb_hid_mouse = usb_hid_mouse_ref.as_mut().unwrap();
+    /// Whether a command started by `process_cmd` is still running
+    pub fn is_busy(&self) -> bool {
+        self.job.is_some()
+    }
 
-        let report = MouseReport {
-            buttons: btn.bits(),
-            x,
-            y,
-            wheel: 0,
+    /// Advances running commands, returning their result once finished
+    ///
+    /// Each report is pushed only after the host has fetched the previous one,
+    /// so every key press and release reaches the host.
+    pub fn poll(&mut self, hid: &mut impl Mutex<T = HidClasses>) -> Option<Result<(), ExecError>> {
+        self.release_idle_floor();
+        self.slide_timer.poll();
+        if let Some(leds) = keyboard_leds::take() {
+            debug!("Keyboard LEDs: {:?}", leds);
+        }
+
+        self.release_silent();
+        let now = clock::millis();
+        while let Some(timer) = self.timers.expire(now) {
+            match self.fire(hid, now, timer) {
+                Ok(()) => (),
+                Err(ExecError::Usb(UsbError::WouldBlock)) => {
+                    self.timers.schedule(now, RETRY_MS, timer).ok();
+                }
+                Err(e) => debug!("{:?} failed: {}", timer, e),
+            }
+        }
+
+        // Its reports would be lost before the host has resumed
+        if usb_power::is_resuming() {
+            return None;
+        }
+        let mut job = self.job.take()?;
+        let held = self.held();
+        let result = match &mut job {
+            Job::Typing(job) => job.step(hid, self.layout.layout(), held.modifiers, &held.keys),
+            Job::Tap(job) => job.step(hid, &held, &mut self.timers),
         };
 
-        debug!("Send report: {:?}", &report);
+        match result {
+            Ok(false) | Err(ExecError::Usb(UsbError::WouldBlock)) => {
+                self.job = Some(job);
+                None
+            }
+            result => {
+                // A tap which failed may have left its release behind
+                self.timers.cancel(|&timer| timer == Timer::TapRelease);
+                Some(result.map(|_| ()))
+            }
+        }
+    }
 
-        if let Err(e) = usb_hid_mouse.push_input(&report) {
-            error!("Mouse Report Error: {:?}", e);
+    /// Runs a timer which is due
+    fn fire(
+        &mut self,
+        hid: &mut impl Mutex<T = HidClasses>,
+        now: u32,
+        timer: Timer,
+    ) -> Result<(), ExecError> {
+        match timer {
+            Timer::TapRelease => {
+                if let Some(Job::Tap(job)) = &mut self.job {
+                    job.state = TapState::Release;
+                }
+            }
+            Timer::Repeat {
+                source,
+                usage,
+                pressed,
+            } => {
+                if self.held[source.index()].consumer != usage {
+                    return Ok(());
+                }
+                if usb_power::is_resuming() {
+                    self.timers.schedule(now, RETRY_MS, timer).ok();
+                    return Ok(());
+                }
+                let report = if pressed { usage } else { 0 };
+                hid.lock(|hid| hid.send_consumer_report(report))?;
+                let next = Timer::Repeat {
+                    source,
+                    usage,
+                    pressed: !pressed,
+                };
+                self.timers.schedule(now, REPEAT_INTERVAL_MS / 2, next).ok();
+            }
+            // Waits for a running job, so its reports don't interleave, and
+            // for the host to resume
+            Timer::Resend(_) if self.is_busy() || usb_power::is_resuming() => {
+                self.timers.schedule(now, RETRY_MS, timer).ok();
+            }
+            Timer::Resend(kind) => self.send_held(hid, kind)?,
         }
-    });
-}
+        Ok(())
+    }
 
-fn send_wheel_report(wheel: i8, btn: MouseButtons) {
-    free(|cs| {
-        let mut usb_hid_mouse_ref = USB_HID_MOUSE.borrow(cs).borrow_mut();
-        let usb_hid_mouse = usb_hid_mouse_ref.as_mut().unwrap();
+    /// Sends the `kind` report with what all sources hold down
+    fn send_held(
+        &self,
+        hid: &mut impl Mutex<T = HidClasses>,
+        kind: ReportKind,
+    ) -> Result<(), ExecError> {
+        let all = self.held();
+        hid.lock(|hid| match kind {
+            ReportKind::Mouse => hid.send_mouse_report(0, 0, all.mouse),
+            ReportKind::Keyboard => hid.send_kbd_report(all.modifiers, &all.keys),
+            ReportKind::Consumer => hid.send_consumer_report(all.consumer),
+            ReportKind::System => hid.send_system_report(all.system),
+        })?;
+        Ok(())
+    }
 
-        let report = MouseReport {
-            buttons: btn.bits(),
-            wheel,
-            ..MouseReport::default()
-        };
+    /// Sends the `kind` report from `poll`, once no job is running and the
+    /// host is awake and has room
+    fn resend(&mut self, kind: ReportKind) {
+        self.timers.cancel(|&timer| timer == Timer::Resend(kind));
+        if self
+            .timers
+            .schedule(clock::millis(), 0, Timer::Resend(kind))
+            .is_err()
+        {
+            warn!("No timer left to send the {:?} report", kind);
+        }
+    }
 
-        debug!("Send report: {:?}", &report);
+    /// Sends the `kind` report after what is held down changed, or leaves it
+    /// to `poll` while the host wakes up or a job sends the same kind
+    fn send_changed(
+        &mut self,
+        hid: &mut impl Mutex<T = HidClasses>,
+        kind: ReportKind,
+    ) -> Result<(), ExecError> {
+        if usb_power::is_resuming() || (self.is_busy() && kind != ReportKind::Mouse) {
+            self.resend(kind);
+            Ok(())
+        } else {
+            self.send_held(hid, kind)
+        }
+    }
 
-        if let Err(e) = usb_hid_mouse.push_input(&report) {
-            error!("Wheel Report Error: {:?}", e);
+    /// Releases everything held down by presenters silent for `release_ms`,
+    /// such as one gone out of range between `kd` and `ku`
+    ///
+    /// The serial port is left alone, its host says when to release.
+    fn release_silent(&mut self) {
+        if self.release_ms == 0 {
+            return;
         }
-    });
-}
+        for pipe in 0..PIPE_COUNT as u8 {
+            let source = Source::Pipe(pipe);
+            let held = self.held[source.index()];
+            if held.is_empty()
+                || self.last_heard[usize::from(pipe)].elapsed_ms() < u32::from(self.release_ms)
+            {
+                continue;
+            }
+
+            warn!(
+                "Nothing from {} for {} ms, releasing all",
+                source, self.release_ms
+            );
+            self.held[source.index()] = Held::new();
+            self.stop_repeat(source);
+            for kind in ReportKind::ALL
+                .iter()
+                .copied()
+                .filter(|&kind| held.holds(kind))
+            {
+                self.resend(kind);
+            }
+        }
+    }
+
+    /// Stops the repeat of the consumer usage held by `source`
+    fn stop_repeat(&mut self, source: Source) {
+        self.timers.cancel(
+            |timer| matches!(timer, Timer::Repeat { source: repeating, .. } if *repeating == source),
+        );
+    }
+
+    pub fn process_cmd<H, R>(
+        &mut self,
+        res: &mut Shared<'_, H, R>,
+        source: Source,
+        cmd: Commands,
+    ) -> Result<(), ExecError>
+    where
+        H: Mutex<T = HidClasses>,
+        R: Mutex<T = NRF24Mode<NRF24Device>>,
+    {
+        if let Source::Pipe(pipe) = source {
+            if !cmd.is_wireless() {
+                return Err(ExecError::NotWireless);
+            }
+            self.heard(pipe);
+        }
+        self.take_floor(source)?;
+
+        // Keyboard, consumer and system reports would interleave with the ones of a running job
+        let uses_job_reports = matches!(
+            cmd,
+            Commands::KeyDown(_)
+                | Commands::KeyUp(_)
+                | Commands::Type(_)
+                | Commands::Tap(_)
+                | Commands::ConsumerDown(_)
+                | Commands::ConsumerUp(_)
+                | Commands::ConsumerTap(_)
+                | Commands::SystemDown(_)
+                | Commands::SystemUp(_)
+                | Commands::SystemTap(_)
+        );
+        // Releases still count, or keys would stay down on the host
+        let is_release = matches!(
+            cmd,
+            Commands::KeyUp(_) | Commands::ConsumerUp(_) | Commands::SystemUp(_)
+        );
+        if uses_job_reports && !is_release && self.is_busy() {
+            return Err(ExecError::Busy);
+        }
+
+        // Commands sending reports wake up a sleeping host. What they hold
+        // down is sent once it has resumed, and moves are dropped.
+        let sends_reports = uses_job_reports
+            || matches!(
+                cmd,
+                Commands::MouseDown(_)
+                    | Commands::MouseUp(_)
+                    | Commands::AbsMove(..)
+                    | Commands::RelMove(..)
+                    | Commands::Wheel(_)
+            );
+        if sends_reports {
+            usb_power::remote_wakeup();
+        }
+
+        let held = &mut self.held[source.index()];
+        match cmd {
+            Commands::MouseDown(btn) => {
+                held.mouse |= btn;
+                self.send_changed(&mut res.hid, ReportKind::Mouse)?;
+            }
+            Commands::MouseUp(btn) => {
+                held.mouse -= btn;
+                self.send_changed(&mut res.hid, ReportKind::Mouse)?;
+            }
+            Commands::KeyDown(key) => {
+                held.press(key);
+                self.send_changed(&mut res.hid, ReportKind::Keyboard)?;
+            }
+            Commands::KeyUp(key) => {
+                held.release(key);
+                self.send_changed(&mut res.hid, ReportKind::Keyboard)?;
+            }
+            Commands::AbsMove(x, y) => {
+                res.hid.lock(|hid| hid.send_cursor_report(x, y))?;
+            }
+            Commands::RelMove(x, y) => {
+                res.hid
+                    .lock(|hid| hid.send_mouse_report(x, y, self.held().mouse))?;
+            }
+            Commands::Wheel(w) => {
+                res.hid
+                    .lock(|hid| hid.send_wheel_report(w, self.held().mouse))?;
+            }
+            Commands::Type(text) => {
+                self.job = Some(Job::Typing(TypingJob::new(text)));
+            }
+            Commands::SetLayout(layout) => {
+                info!("Keyboard layout: {}", layout.layout().name());
+                self.layout = layout;
+            }
+            Commands::Help(HelpTopic::Keys) => {
+                print_names("KEYS", KEY_NAMES);
+                BlockingSerialWriter
+                    .write_str("NOTE held keys are repeated by the host, not here\r\n")
+                    .ok();
+            }
+            Commands::Help(HelpTopic::Consumer) => {
+                print_names("CC", CONSUMER_NAMES);
+                let repeating = CONSUMER_NAMES
+                    .iter()
+                    .filter(|(_, usage)| REPEAT_USAGES.contains(usage));
+                let mut writer = BlockingSerialWriter;
+                writer.write_str("NOTE repeated while held:").ok();
+                for (name, _) in repeating {
+                    write!(writer, " {}", name).ok();
+                }
+                writer.write_str("\r\n").ok();
+            }
+            Commands::Help(HelpTopic::System) => {
+                print_names("SC", SYSTEM_NAMES);
+            }
+            Commands::Tap(chord) => {
+                self.job = Some(Job::Tap(TapJob::new(Tap::Chord(chord))));
+            }
+            Commands::ConsumerDown(usage) => {
+                held.consumer = usage;
+                self.stop_repeat(source);
+                if usb_power::is_resuming() {
+                    self.resend(ReportKind::Consumer);
+                } else {
+                    res.hid.lock(|hid| hid.send_consumer_report(usage))?;
+                }
+                if REPEAT_USAGES.contains(&usage) {
+                    let timer = Timer::Repeat {
+                        source,
+                        usage,
+                        pressed: false,
+                    };
+                    self.timers
+                        .schedule(clock::millis(), REPEAT_DELAY_MS, timer)
+                        .ok();
+                }
+            }
+            Commands::ConsumerUp(usage) => {
+                if held.consumer == usage {
+                    held.consumer = 0;
+                    self.stop_repeat(source);
+                }
+                self.send_changed(&mut res.hid, ReportKind::Consumer)?;
+            }
+            Commands::ConsumerTap(usage) => {
+                self.job = Some(Job::Tap(TapJob::new(Tap::Consumer(usage))));
+            }
+            Commands::SystemDown(usage) => {
+                held.system = usage;
+                if usb_power::is_resuming() {
+                    self.resend(ReportKind::System);
+                } else {
+                    res.hid.lock(|hid| hid.send_system_report(usage))?;
+                }
+            }
+            Commands::SystemUp(usage) => {
+                if held.system == usage {
+                    held.system = 0;
+                }
+                self.send_changed(&mut res.hid, ReportKind::System)?;
+            }
+            Commands::SystemTap(usage) => {
+                self.job = Some(Job::Tap(TapJob::new(Tap::System(usage))));
+            }
+            Commands::Stats => {
+                LINK_STATS.print();
+            }
+            Commands::Config(cmd) => {
+                self.run_config(res, cmd)?;
+            }
+            Commands::Timer(TimerAction::Show) => {
+                let mut writer = BlockingSerialWriter;
+                write!(
+                    writer,
+                    "TIMER {} {}\r\n",
+                    self.slide_timer.seconds(),
+                    if self.slide_timer.is_running() {
+                        "running"
+                    } else {
+                        "stopped"
+                    }
+                )
+                .ok();
+            }
+            Commands::Timer(TimerAction::Start) => self.slide_timer.start(),
+            Commands::Timer(TimerAction::Stop) => self.slide_timer.stop(),
+            Commands::Timer(TimerAction::Reset) => self.slide_timer.reset(),
+            Commands::Leds => {
+                let mut writer = BlockingSerialWriter;
+                writer.write_str("LEDS").ok();
+                for &(name, led) in &[
+                    ("num", KeyboardLeds::NUM_LOCK),
+                    ("caps", KeyboardLeds::CAPS_LOCK),
+                    ("scroll", KeyboardLeds::SCROLL_LOCK),
+                ] {
+                    let state = if self.keyboard_leds().contains(led) {
+                        "on"
+                    } else {
+                        "off"
+                    };
+                    write!(writer, " {}={}", name, state).ok();
+                }
+                writer.write_str("\r\n").ok();
+            }
+            Commands::Radio => {
+                res.radio
+                    .lock(radio::read)
+                    .map_err(|_| ExecError::Radio)?
+                    .print();
+            }
+        }
+        Ok(())
+    }
 
-fn send_kbd_report(modifiers: KeyboardModifiers, keys: &[u8; 6]) {
-    free(|cs| {
-        let mut usb_hid_kbd_ref = USB_HID_KBD.borrow(cs).borrow_mut();
-        let usb_hid_kbd = usb_hid_kbd_ref.as_mut().unwrap();
+    fn run_config<H, R>(
+        &mut self,
+        res: &mut Shared<'_, H, R>,
+        cmd: ConfigCommand,
+    ) -> Result<(), ExecError>
+    where
+        R: Mutex<T = NRF24Mode<NRF24Device>>,
+    {
+        let config = &mut *res.config;
+        match cmd {
+            ConfigCommand::Get(key) => {
+                config.print(key);
+                return Ok(());
+            }
+            ConfigCommand::Set(value) => {
+                config.set(value);
+                match value {
+                    ConfigValue::Log(log_level) => log::set_max_level(log_level),
+                    ConfigValue::Layout(layout) => self.layout = layout,
+                    ConfigValue::FloorControl(on) => self.set_floor_control(on),
+                    ConfigValue::ReleaseTimeout(ms) => self.release_ms = ms,
+                    ConfigValue::Address(_) | ConfigValue::Key(_) => {
+                        rekey(res.secure_link, res.seq_tracker, config)
+                    }
+                    // Other presenters may answer on pipes 1 to 5 now
+                    ConfigValue::PipeAddress(_) => {
+                        for pipe in 1..PIPE_COUNT as u8 {
+                            res.seq_tracker.reset(pipe);
+                        }
+                    }
+                    _ => (),
+                }
+            }
+            ConfigCommand::Save => config.save()?,
+            ConfigCommand::Reset => {
+                *config = Config::default();
+                log::set_max_level(config.log_level);
+                self.layout = config.layout;
+                self.set_floor_control(config.floor_control);
+                self.release_ms = config.release_ms;
+                rekey(res.secure_link, res.seq_tracker, config);
+            }
+        }
 
-        let report = KeyboardReport {
-            modifier: modifiers.bits(),
-            keycodes: *keys,
-            leds: 0,
+        let radio_changed = match cmd {
+            ConfigCommand::Set(value) => value.key().is_radio(),
+            ConfigCommand::Reset => true,
+            _ => false,
         };
+        if radio_changed {
+            let registers = apply_radio(&mut res.radio, config)?;
+            if let ConfigCommand::Set(value) = cmd {
+                config.print(Some(value.key()));
+            }
+            registers.print();
+        }
+        Ok(())
+    }
 
-        debug!("Send report: {:?}", &report);
+    fn set_floor_control(&mut self, on: bool) {
+        self.floor_control = on;
+        self.floor = None;
+    }
 
-        if let Err(e) = usb_hid_kbd.push_input(&report) {
-            error!("Keyboard Report Error: {:?}", e);
+    /// Checks that the source may send commands under floor control. A
+    /// pipe gets the floor when nobody else has it.
+    fn take_floor(&mut self, source: Source) -> Result<(), ExecError> {
+        let pipe = match source {
+            Source::Pipe(pipe) if self.floor_control => pipe,
+            _ => return Ok(()),
+        };
+        match self.floor {
+            Some((holder, _)) if holder != pipe => return Err(ExecError::NoFloor(holder)),
+            Some(_) => (),
+            None => info!("Pipe {} has the floor", pipe),
         }
-    });
+        self.floor = Some((pipe, Instant::now()));
+        Ok(())
+    }
+
+    /// Gives the floor up for a pipe which went quiet without holding
+    /// anything down
+    fn release_idle_floor(&mut self) {
+        if let Some((holder, last_at)) = self.floor {
+            if last_at.elapsed_ms() >= FLOOR_IDLE_MS
+                && self.held[Source::Pipe(holder).index()].is_empty()
+            {
+                info!("Pipe {} left the floor", holder);
+                self.floor = None;
+            }
+        }
+    }
+}
+
+/// Lists a name table over serial, 8 names per line after `prefix`
+fn print_names<T>(prefix: &str, table: &[(&str, T)]) {
+    let mut writer = BlockingSerialWriter;
+    for names in table.chunks(8) {
+        writer.write_str(prefix).ok();
+        for (name, _) in names {
+            write!(writer, " {}", name).ok();
+        }
+        writer.write_str("\r\n").ok();
+    }
+}
+
+/// Keys the pipes with the paired key and forgets their sequence numbers,
+/// which belonged to the old presenters
+fn rekey(secure_link: &mut SecureLink, seq_tracker: &mut SeqTracker, config: &mut Config) {
+    secure_link.set_keys(&config.paired.key);
+    config.counter_floor = secure_link.floors();
+    for pipe in 0..PIPE_COUNT as u8 {
+        seq_tracker.reset(pipe);
+    }
+}
+
+/// Writes the radio settings and reads the registers back
+fn apply_radio(
+    radio: &mut impl Mutex<T = NRF24Mode<NRF24Device>>,
+    config: &Config,
+) -> Result<Registers, ExecError> {
+    radio
+        .lock(|nrf24l01| radio::apply(nrf24l01, config))
+        .map_err(|_| ExecError::Radio)
 }
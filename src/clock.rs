@@ -0,0 +1,42 @@
+use stm32l4xx_hal::stm32::{RCC, TIM2};
+
+/// Core clock frequency set up in `main`
+pub const SYSCLK_HZ: u32 = 48_000_000;
+
+/// Starts TIM2 counting milliseconds for `millis`
+pub fn start() {
+    let rcc = unsafe { &(*RCC::ptr()) };
+    rcc.apb1enr1.modify(|_, w| w.tim2en().set_bit());
+    let tim2 = unsafe { &(*TIM2::ptr()) };
+    // APB1 runs at half of SYSCLK, so its timers get SYSCLK
+    tim2.psc
+        .write(|w| unsafe { w.psc().bits((SYSCLK_HZ / 1000 - 1) as u16) });
+    tim2.arr.write(|w| unsafe { w.bits(u32::MAX) });
+    // Load the prescaler
+    tim2.egr.write(|w| w.ug().set_bit());
+    tim2.cr1.modify(|_, w| w.cen().set_bit());
+}
+
+/// Milliseconds since `start`, wrapping around after about 49 days
+pub fn millis() -> u32 {
+    let tim2 = unsafe { &(*TIM2::ptr()) };
+    tim2.cnt.read().bits()
+}
+
+/// Point in time read from the TIM2 millisecond counter
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Instant(u32);
+
+impl Instant {
+    pub fn now() -> Self {
+        Instant(millis())
+    }
+
+    pub fn add_ms(self, ms: u32) -> Self {
+        Instant(self.0.wrapping_add(ms))
+    }
+
+    pub fn elapsed_ms(self) -> u32 {
+        millis().wrapping_sub(self.0)
+    }
+}
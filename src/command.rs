@@ -1,5 +1,20 @@
-use crate::hid_report::MouseButtons;
-use core::str::FromStr;
+use crate::config::{
+    self, ConfigCommand, ConfigKey, ConfigValue, Crc, Rate, Retransmit, MAX_CHANNEL,
+    MAX_RETRANSMIT_COUNT, MAX_TX_POWER,
+};
+use crate::hid_report::{KeyboardModifiers, MouseButtons};
+use crate::layout::LayoutId;
+use crate::link::PIPE_COUNT;
+use crate::packet::KEEP_ALIVE_MS;
+use core::{convert::TryFrom, fmt, str, str::FromStr};
+
+/// Maximum length in bytes of a text argument
+pub const TEXT_CAPACITY: usize = 56;
+/// Length in bytes of the longest valid request, a tagged `type` with a full
+/// text
+pub const MAX_REQUEST_LEN: usize = "#65535 type ".len() + TEXT_CAPACITY;
+/// How long `kp` holds its keys unless told otherwise
+pub const DEFAULT_HOLD_MS: u16 = 50;
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum Commands {
@@ -10,137 +25,818 @@ pub enum Commands {
     KeyDown(u8),
     KeyUp(u8),
     Wheel(i8),
+    Type(Text),
+    SetLayout(LayoutId),
+    Help(HelpTopic),
+    Tap(Chord),
+    ConsumerDown(u16),
+    ConsumerUp(u16),
+    ConsumerTap(u16),
+    SystemDown(u8),
+    SystemUp(u8),
+    SystemTap(u8),
+    Stats,
+    Config(ConfigCommand),
+    /// Reads the nRF24 registers back
+    Radio,
+    Timer(TimerAction),
+    /// Shows the lock LEDs set by the host
+    Leds,
+}
+
+impl Commands {
+    /// Whether the command may come from a presenter: the ones sending HID
+    /// reports, the layout they type with and the slide timer. Settings and
+    /// serial output are left to the host.
+    pub fn is_wireless(&self) -> bool {
+        !matches!(
+            self,
+            Commands::Help(_)
+                | Commands::Stats
+                | Commands::Config(_)
+                | Commands::Radio
+                | Commands::Leds
+        )
+    }
+}
+
+/// What `timer` does with the slide timer
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TimerAction {
+    Show,
+    Start,
+    Stop,
+    Reset,
+}
+
+/// Name tables which can be listed with `help`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HelpTopic {
+    Keys,
+    Consumer,
+    System,
+}
+
+/// Modifiers and keys pressed together and released after `hold_ms`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Chord {
+    pub modifiers: KeyboardModifiers,
+    pub keys: [u8; 6],
+    pub hold_ms: u16,
+}
+
+/// Key names accepted by `kd` and `ku`, following `hid_keycode.py`
+pub const KEY_NAMES: &[(&str, u8)] = &[
+    ("A", 0x04),
+    ("B", 0x05),
+    ("C", 0x06),
+    ("D", 0x07),
+    ("E", 0x08),
+    ("F", 0x09),
+    ("G", 0x0A),
+    ("H", 0x0B),
+    ("I", 0x0C),
+    ("J", 0x0D),
+    ("K", 0x0E),
+    ("L", 0x0F),
+    ("M", 0x10),
+    ("N", 0x11),
+    ("O", 0x12),
+    ("P", 0x13),
+    ("Q", 0x14),
+    ("R", 0x15),
+    ("S", 0x16),
+    ("T", 0x17),
+    ("U", 0x18),
+    ("V", 0x19),
+    ("W", 0x1A),
+    ("X", 0x1B),
+    ("Y", 0x1C),
+    ("Z", 0x1D),
+    ("ONE", 0x1E),
+    ("TWO", 0x1F),
+    ("THREE", 0x20),
+    ("FOUR", 0x21),
+    ("FIVE", 0x22),
+    ("SIX", 0x23),
+    ("SEVEN", 0x24),
+    ("EIGHT", 0x25),
+    ("NINE", 0x26),
+    ("ZERO", 0x27),
+    ("ENTER", 0x28),
+    ("ESCAPE", 0x29),
+    ("BACKSPACE", 0x2A),
+    ("TAB", 0x2B),
+    ("SPACEBAR", 0x2C),
+    ("MINUS", 0x2D),
+    ("EQUALS", 0x2E),
+    ("LEFT_BRACKET", 0x2F),
+    ("RIGHT_BRACKET", 0x30),
+    ("BACKSLASH", 0x31),
+    ("POUND", 0x32),
+    ("SEMICOLON", 0x33),
+    ("QUOTE", 0x34),
+    ("GRAVE_ACCENT", 0x35),
+    ("COMMA", 0x36),
+    ("PERIOD", 0x37),
+    ("FORWARD_SLASH", 0x38),
+    ("CAPS_LOCK", 0x39),
+    ("F1", 0x3A),
+    ("F2", 0x3B),
+    ("F3", 0x3C),
+    ("F4", 0x3D),
+    ("F5", 0x3E),
+    ("F6", 0x3F),
+    ("F7", 0x40),
+    ("F8", 0x41),
+    ("F9", 0x42),
+    ("F10", 0x43),
+    ("F11", 0x44),
+    ("F12", 0x45),
+    ("PRINT_SCREEN", 0x46),
+    ("SCROLL_LOCK", 0x47),
+    ("PAUSE", 0x48),
+    ("INSERT", 0x49),
+    ("HOME", 0x4A),
+    ("PAGE_UP", 0x4B),
+    ("DELETE", 0x4C),
+    ("END", 0x4D),
+    ("PAGE_DOWN", 0x4E),
+    ("RIGHT_ARROW", 0x4F),
+    ("LEFT_ARROW", 0x50),
+    ("DOWN_ARROW", 0x51),
+    ("UP_ARROW", 0x52),
+    ("KEYPAD_NUMLOCK", 0x53),
+    ("KEYPAD_FORWARD_SLASH", 0x54),
+    ("KEYPAD_ASTERISK", 0x55),
+    ("KEYPAD_MINUS", 0x56),
+    ("KEYPAD_PLUS", 0x57),
+    ("KEYPAD_ENTER", 0x58),
+    ("KEYPAD_ONE", 0x59),
+    ("KEYPAD_TWO", 0x5A),
+    ("KEYPAD_THREE", 0x5B),
+    ("KEYPAD_FOUR", 0x5C),
+    ("KEYPAD_FIVE", 0x5D),
+    ("KEYPAD_SIX", 0x5E),
+    ("KEYPAD_SEVEN", 0x5F),
+    ("KEYPAD_EIGHT", 0x60),
+    ("KEYPAD_NINE", 0x61),
+    ("KEYPAD_ZERO", 0x62),
+    ("KEYPAD_PERIOD", 0x63),
+    ("KEYPAD_BACKSLASH", 0x64),
+    ("APPLICATION", 0x65),
+    ("POWER", 0x66),
+    ("KEYPAD_EQUALS", 0x67),
+    ("F13", 0x68),
+    ("F14", 0x69),
+    ("F15", 0x6A),
+    ("F16", 0x6B),
+    ("F17", 0x6C),
+    ("F18", 0x6D),
+    ("F19", 0x6E),
+    ("LEFT_CONTROL", 0xE0),
+    ("LEFT_SHIFT", 0xE1),
+    ("LEFT_ALT", 0xE2),
+    ("LEFT_GUI", 0xE3),
+    ("RIGHT_CONTROL", 0xE4),
+    ("RIGHT_SHIFT", 0xE5),
+    ("RIGHT_ALT", 0xE6),
+    ("RIGHT_GUI", 0xE7),
+    // Short aliases
+    ("ESC", 0x29),
+    ("SPACE", 0x2C),
+    ("LEFT", 0x50),
+    ("RIGHT", 0x4F),
+    ("UP", 0x52),
+    ("DOWN", 0x51),
+    ("CTRL", 0xE0),
+    ("SHIFT", 0xE1),
+    ("ALT", 0xE2),
+    ("GUI", 0xE3),
+];
+
+/// Consumer Page usage names accepted by `cc`
+pub const CONSUMER_NAMES: &[(&str, u16)] = &[
+    ("BRIGHTNESS_UP", 0x6F),
+    ("BRIGHTNESS_DOWN", 0x70),
+    ("NEXT_TRACK", 0xB5),
+    ("PREV_TRACK", 0xB6),
+    ("STOP", 0xB7),
+    ("PLAY_PAUSE", 0xCD),
+    ("MUTE", 0xE2),
+    ("VOLUME_UP", 0xE9),
+    ("VOLUME_DOWN", 0xEA),
+];
+
+/// Highest Consumer Page usage declared in the report descriptor
+pub const CONSUMER_USAGE_MAX: u16 = 0x3FF;
+
+/// Generic Desktop System Control usage names accepted by `sc`
+pub const SYSTEM_NAMES: &[(&str, u8)] = &[("POWER_DOWN", 0x81), ("SLEEP", 0x82), ("WAKE_UP", 0x83)];
+
+/// Looks up the usage ID of a case-insensitive name
+fn usage_from_name<T: Copy>(names: &[(&str, T)], name: &str) -> Option<T> {
+    names
+        .iter()
+        .find(|(usage_name, _)| usage_name.eq_ignore_ascii_case(name))
+        .map(|&(_, usage)| usage)
+}
+
+/// Fixed-capacity UTF-8 string argument
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct Text {
+    buf: [u8; TEXT_CAPACITY],
+    len: u8,
+}
+
+impl Text {
+    pub fn new(s: &str) -> Option<Self> {
+        if s.len() > TEXT_CAPACITY {
+            return None;
+        }
+        let mut buf = [0u8; TEXT_CAPACITY];
+        buf[..s.len()].copy_from_slice(s.as_bytes());
+        Some(Self {
+            buf,
+            len: s.len() as u8,
+        })
+    }
+
+    pub fn as_str(&self) -> &str {
+        // Safety: The buffer is always copied from a `&str`
+        unsafe { str::from_utf8_unchecked(&self.buf[..self.len as usize]) }
+    }
+}
+
+impl fmt::Debug for Text {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self.as_str(), f)
+    }
+}
+
+/// A serial command line together with its optional sequence id
+///
+/// Lines prefixed with `#<id>` ask for an acknowledgement: the firmware answers
+/// `OK <id>` once the command has been executed, or `ERR <id> <code> <detail>`.
+/// Errors of untagged lines are reported as `ERR - <code> <detail>`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Request {
+    pub seq: Option<u16>,
+    pub cmd: Commands,
+}
+
+impl Request {
+    /// Parses a command line, keeping the sequence id even if the command is
+    /// invalid so that the error can still be acknowledged.
+    pub fn parse(line: &str) -> (Option<u16>, Result<Self, ParseError>) {
+        let (seq, cmdline) = match split_seq(line) {
+            Ok(split) => split,
+            Err(e) => return (None, Err(e)),
+        };
+
+        let req = cmdline.parse::<Commands>().map(|cmd| Request { seq, cmd });
+        (seq, req)
+    }
+
+    /// Reads only the sequence id from the start of a line, e.g. to
+    /// acknowledge one which is too long
+    pub fn parse_seq(start: &str) -> Option<u16> {
+        split_seq(start).ok().and_then(|(seq, _)| seq)
+    }
+}
+
+/// Splits the `#<id>` prefix off a line
+fn split_seq(line: &str) -> Result<(Option<u16>, &str), ParseError> {
+    let line = line.trim_start();
+    match line.strip_prefix('#') {
+        Some(rest) => {
+            let (seq_str, cmdline) = split_verb(rest);
+            match seq_str.parse::<u16>() {
+                Ok(seq) => Ok((Some(seq), cmdline)),
+                Err(_) => Err(ParseError::InvalidSequence),
+            }
+        }
+        None => Ok((None, line)),
+    }
+}
+
+/// Reasons for rejecting a command line.
+///
+/// Argument positions are 1-based, counted from the first argument after the
+/// command verb.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ParseError {
+    Empty,
+    UnknownCommand,
+    MissingArgument(usize),
+    InvalidNumber(usize),
+    OutOfRange(usize),
+    InvalidButtons(usize),
+    TrailingArgument(usize),
+    InvalidSequence,
+    UnknownName(usize),
+    /// Longer than `MAX_REQUEST_LEN`
+    LineTooLong,
+    InvalidUtf8,
+}
+
+impl ParseError {
+    /// Numeric code reported to the host in `ERR` lines
+    pub const fn code(&self) -> u8 {
+        match self {
+            ParseError::Empty => 1,
+            ParseError::UnknownCommand => 2,
+            ParseError::MissingArgument(_) => 3,
+            ParseError::InvalidNumber(_) => 4,
+            ParseError::OutOfRange(_) => 5,
+            ParseError::InvalidButtons(_) => 6,
+            ParseError::TrailingArgument(_) => 7,
+            ParseError::InvalidSequence => 8,
+            ParseError::UnknownName(_) => 9,
+            ParseError::LineTooLong => 10,
+            ParseError::InvalidUtf8 => 11,
+        }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::Empty => f.write_str("empty command"),
+            ParseError::UnknownCommand => f.write_str("unknown command"),
+            ParseError::MissingArgument(pos) => write!(f, "missing argument {}", pos),
+            ParseError::InvalidNumber(pos) => write!(f, "argument {} is not a number", pos),
+            ParseError::OutOfRange(pos) => write!(f, "argument {} is out of range", pos),
+            ParseError::InvalidButtons(pos) => {
+                write!(f, "argument {} has invalid button bits", pos)
+            }
+            ParseError::TrailingArgument(pos) => write!(f, "unexpected argument {}", pos),
+            ParseError::InvalidSequence => f.write_str("invalid sequence id"),
+            ParseError::UnknownName(pos) => write!(f, "argument {} is not a known name", pos),
+            ParseError::LineTooLong => f.write_str("line too long"),
+            ParseError::InvalidUtf8 => f.write_str("line is not UTF-8"),
+        }
+    }
 }
 
 impl FromStr for Commands {
-    type Err = ();
+    type Err = ParseError;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let mut argv = s.split_ascii_whitespace();
-        if let Some(cmd) = argv.next() {
-            match cmd {
-                "ma" => parse_ma(argv),
-                "mr" => parse_mr(argv),
-                "md" => parse_md(argv),
-                "mu" => parse_mu(argv),
-                "kd" => parse_kd(argv),
-                "ku" => parse_ku(argv),
-                "wh" => parse_wh(argv),
-                _ => Err(()),
-            }
+        let (cmd, rest) = split_verb(s.trim_start());
+        if cmd == "type" {
+            // The text is taken verbatim, so it is not split into arguments
+            return parse_type(rest);
+        }
+
+        if !cmd.is_empty() {
+            let mut args = Arguments::new(rest.split_ascii_whitespace());
+            let cmd = match cmd {
+                "ma" => parse_ma(&mut args),
+                "mr" => parse_mr(&mut args),
+                "md" => parse_md(&mut args),
+                "mu" => parse_mu(&mut args),
+                "kd" => parse_kd(&mut args),
+                "kp" => parse_kp(&mut args),
+                "ku" => parse_ku(&mut args),
+                "wh" => parse_wh(&mut args),
+                "cc" => parse_cc(&mut args),
+                "sc" => parse_sc(&mut args),
+                "layout" => parse_layout(&mut args),
+                "help" => parse_help(&mut args),
+                "stats" => Ok(Commands::Stats),
+                "cfg" => parse_cfg(&mut args),
+                "rf" => Ok(Commands::Radio),
+                "timer" => parse_timer(&mut args),
+                "leds?" => Ok(Commands::Leds),
+                _ => Err(ParseError::UnknownCommand),
+            }?;
+            args.finish()?;
+            Ok(cmd)
         } else {
-            Err(())
+            Err(ParseError::Empty)
         }
     }
 }
 
-fn parse_ma<'a, I>(mut iter: I) -> Result<Commands, ()>
+/// Splits a line into its first word and the remaining text
+fn split_verb(s: &str) -> (&str, &str) {
+    match s.find(|c: char| c.is_ascii_whitespace()) {
+        Some(idx) => s.split_at(idx),
+        None => (s, ""),
+    }
+}
+
+/// What to do with the usage given to `cc` and `sc`
+enum Action {
+    Down,
+    Up,
+    Tap,
+}
+
+/// Argument iterator which keeps track of the current argument position
+struct Arguments<I> {
+    iter: I,
+    pos: usize,
+}
+
+impl<'a, I> Arguments<I>
 where
     I: Iterator<Item = &'a str>,
 {
-    let arg_x = iter.next();
-    let arg_y = iter.next();
+    fn new(iter: I) -> Self {
+        Self { iter, pos: 0 }
+    }
+
+    fn next(&mut self) -> Result<&'a str, ParseError> {
+        self.pos += 1;
+        self.iter
+            .next()
+            .ok_or(ParseError::MissingArgument(self.pos))
+    }
+
+    fn number<T: TryFrom<i64>>(&mut self) -> Result<T, ParseError> {
+        let arg = self.next()?;
+        parse_integer(arg, 10, self.pos)
+    }
+
+    fn optional_number<T: TryFrom<i64>>(&mut self) -> Result<Option<T>, ParseError> {
+        match self.iter.next() {
+            Some(arg) => {
+                self.pos += 1;
+                parse_integer(arg, 10, self.pos).map(Some)
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn key(&mut self) -> Result<u8, ParseError> {
+        let arg = self.next()?;
+        parse_key(arg, self.pos)
+    }
+
+    /// Parses an optional `down`, `up` or `tap`, defaulting to `tap`
+    fn action(&mut self) -> Result<Action, ParseError> {
+        match self.iter.next() {
+            Some(arg) => {
+                self.pos += 1;
+                match arg {
+                    "down" => Ok(Action::Down),
+                    "up" => Ok(Action::Up),
+                    "tap" => Ok(Action::Tap),
+                    _ => Err(ParseError::UnknownName(self.pos)),
+                }
+            }
+            None => Ok(Action::Tap),
+        }
+    }
 
-    if let Some((arg_x, arg_y)) = arg_x.zip(arg_y) {
-        let x: u16 = arg_x.parse().map_err(|_| ())?;
-        let y: u16 = arg_y.parse().map_err(|_| ())?;
+    fn buttons(&mut self) -> Result<MouseButtons, ParseError> {
+        let btn_bits: u8 = self.number()?;
+        MouseButtons::from_bits(btn_bits).ok_or(ParseError::InvalidButtons(self.pos))
+    }
 
-        Ok(Commands::AbsMove(x, y))
+    fn finish(mut self) -> Result<(), ParseError> {
+        if self.iter.next().is_some() {
+            Err(ParseError::TrailingArgument(self.pos + 1))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+fn parse_key(arg: &str, pos: usize) -> Result<u8, ParseError> {
+    parse_usage(arg, pos, KEY_NAMES)
+}
+
+/// Parses a usage given as a name, a decimal or a `0x` prefixed hex ID
+fn parse_usage<T>(arg: &str, pos: usize, names: &[(&str, T)]) -> Result<T, ParseError>
+where
+    T: TryFrom<i64> + Copy,
+{
+    if let Some(hex) = arg.strip_prefix("0x").or_else(|| arg.strip_prefix("0X")) {
+        parse_integer(hex, 16, pos)
+    } else if arg.starts_with(|c: char| c.is_ascii_digit() || c == '+' || c == '-') {
+        parse_integer(arg, 10, pos)
     } else {
-        Err(())
+        usage_from_name(names, arg).ok_or(ParseError::UnknownName(pos))
     }
 }
 
-fn parse_mr<'a, I>(mut iter: I) -> Result<Commands, ()>
+fn parse_integer<T: TryFrom<i64>>(arg: &str, radix: u32, pos: usize) -> Result<T, ParseError> {
+    let value = i64::from_str_radix(arg, radix).map_err(|_| {
+        if is_integer(arg, radix) {
+            ParseError::OutOfRange(pos)
+        } else {
+            ParseError::InvalidNumber(pos)
+        }
+    })?;
+    T::try_from(value).map_err(|_| ParseError::OutOfRange(pos))
+}
+
+/// Parses exactly `N` bytes given as hex digits
+fn parse_hex<const N: usize>(arg: &str, pos: usize) -> Result<[u8; N], ParseError> {
+    if !arg.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return Err(ParseError::InvalidNumber(pos));
+    }
+    if arg.len() != N * 2 {
+        return Err(ParseError::OutOfRange(pos));
+    }
+    let mut bytes = [0u8; N];
+    for (i, byte) in bytes.iter_mut().enumerate() {
+        *byte = parse_integer(&arg[i * 2..i * 2 + 2], 16, pos)?;
+    }
+    Ok(bytes)
+}
+
+fn is_integer(s: &str, radix: u32) -> bool {
+    let digits = s.strip_prefix(|c| c == '+' || c == '-').unwrap_or(s);
+    !digits.is_empty() && digits.chars().all(|c| c.is_digit(radix))
+}
+
+fn parse_ma<'a, I>(args: &mut Arguments<I>) -> Result<Commands, ParseError>
 where
     I: Iterator<Item = &'a str>,
 {
-    let arg_x = iter.next();
-    let arg_y = iter.next();
+    let x: u16 = args.number()?;
+    let y: u16 = args.number()?;
 
-    if let Some((arg_x, arg_y)) = arg_x.zip(arg_y) {
-        let x: i16 = arg_x.parse().map_err(|_| ())?;
-        let y: i16 = arg_y.parse().map_err(|_| ())?;
+    Ok(Commands::AbsMove(x, y))
+}
 
-        Ok(Commands::RelMove(x, y))
-    } else {
-        Err(())
-    }
+fn parse_mr<'a, I>(args: &mut Arguments<I>) -> Result<Commands, ParseError>
+where
+    I: Iterator<Item = &'a str>,
+{
+    let x: i16 = args.number()?;
+    let y: i16 = args.number()?;
+
+    Ok(Commands::RelMove(x, y))
 }
 
-fn parse_md<'a, I>(mut iter: I) -> Result<Commands, ()>
+fn parse_md<'a, I>(args: &mut Arguments<I>) -> Result<Commands, ParseError>
 where
     I: Iterator<Item = &'a str>,
 {
-    let arg_btn = iter.next();
+    let btn = args.buttons()?;
 
-    if let Some(arg_btn) = arg_btn {
-        let btn_bits: u8 = arg_btn.parse().map_err(|_| ())?;
-        let btn = MouseButtons::from_bits(btn_bits).ok_or(())?;
+    Ok(Commands::MouseDown(btn))
+}
 
-        Ok(Commands::MouseDown(btn))
-    } else {
-        Err(())
-    }
+fn parse_mu<'a, I>(args: &mut Arguments<I>) -> Result<Commands, ParseError>
+where
+    I: Iterator<Item = &'a str>,
+{
+    let btn = args.buttons()?;
+
+    Ok(Commands::MouseUp(btn))
 }
 
-fn parse_mu<'a, I>(mut iter: I) -> Result<Commands, ()>
+fn parse_kd<'a, I>(args: &mut Arguments<I>) -> Result<Commands, ParseError>
 where
     I: Iterator<Item = &'a str>,
 {
-    let arg_btn = iter.next();
+    let keycode = args.key()?;
 
-    if let Some(arg_btn) = arg_btn {
-        let btn_bits: u8 = arg_btn.parse().map_err(|_| ())?;
-        let btn = MouseButtons::from_bits(btn_bits).ok_or(())?;
+    Ok(Commands::KeyDown(keycode))
+}
 
-        Ok(Commands::MouseUp(btn))
-    } else {
-        Err(())
+/// Parses `kp <key>[+<key>...] [hold_ms]`, e.g. `kp LEFT_SHIFT+F5`
+fn parse_kp<'a, I>(args: &mut Arguments<I>) -> Result<Commands, ParseError>
+where
+    I: Iterator<Item = &'a str>,
+{
+    let arg_chord = args.next()?;
+    let pos = args.pos;
+
+    let mut chord = Chord {
+        modifiers: KeyboardModifiers::empty(),
+        keys: [0u8; 6],
+        hold_ms: DEFAULT_HOLD_MS,
+    };
+    let mut key_cnt = 0;
+    for arg_key in arg_chord.split('+') {
+        let key = parse_key(arg_key, pos)?;
+        if KeyboardModifiers::is_modifier(key) {
+            chord.modifiers |= KeyboardModifiers::from_keycode(key);
+        } else if key_cnt < chord.keys.len() {
+            chord.keys[key_cnt] = key;
+            key_cnt += 1;
+        } else {
+            // Too many keys for a single report
+            return Err(ParseError::OutOfRange(pos));
+        }
     }
+    if let Some(hold_ms) = args.optional_number()? {
+        chord.hold_ms = hold_ms;
+    }
+
+    Ok(Commands::Tap(chord))
 }
 
-fn parse_kd<'a, I>(mut iter: I) -> Result<Commands, ()>
+fn parse_ku<'a, I>(args: &mut Arguments<I>) -> Result<Commands, ParseError>
 where
     I: Iterator<Item = &'a str>,
 {
-    let arg_key = iter.next();
+    let keycode = args.key()?;
 
-    if let Some(arg_key) = arg_key {
-        let keycode: u8 = arg_key.parse().map_err(|_| ())?;
+    Ok(Commands::KeyUp(keycode))
+}
 
-        Ok(Commands::KeyDown(keycode))
-    } else {
-        Err(())
+fn parse_wh<'a, I>(args: &mut Arguments<I>) -> Result<Commands, ParseError>
+where
+    I: Iterator<Item = &'a str>,
+{
+    let wheel: i8 = args.number()?;
+
+    Ok(Commands::Wheel(wheel))
+}
+
+fn parse_type(rest: &str) -> Result<Commands, ParseError> {
+    // Only strip the separator, leading spaces are part of the text
+    let text = match rest.chars().next() {
+        Some(c) if c.is_ascii_whitespace() => &rest[1..],
+        _ => rest,
+    };
+    if text.is_empty() {
+        return Err(ParseError::MissingArgument(1));
     }
+    let text = Text::new(text).ok_or(ParseError::OutOfRange(1))?;
+
+    Ok(Commands::Type(text))
 }
 
-fn parse_ku<'a, I>(mut iter: I) -> Result<Commands, ()>
+fn parse_layout<'a, I>(args: &mut Arguments<I>) -> Result<Commands, ParseError>
 where
     I: Iterator<Item = &'a str>,
 {
-    let arg_key = iter.next();
+    let name = args.next()?;
+    let layout = LayoutId::from_name(name).ok_or(ParseError::UnknownName(args.pos))?;
 
-    if let Some(arg_key) = arg_key {
-        let keycode: u8 = arg_key.parse().map_err(|_| ())?;
+    Ok(Commands::SetLayout(layout))
+}
 
-        Ok(Commands::KeyUp(keycode))
-    } else {
-        Err(())
+fn parse_help<'a, I>(args: &mut Arguments<I>) -> Result<Commands, ParseError>
+where
+    I: Iterator<Item = &'a str>,
+{
+    match args.next()? {
+        "keys" => Ok(Commands::Help(HelpTopic::Keys)),
+        "cc" => Ok(Commands::Help(HelpTopic::Consumer)),
+        "sc" => Ok(Commands::Help(HelpTopic::System)),
+        _ => Err(ParseError::UnknownName(args.pos)),
     }
 }
 
-fn parse_wh<'a, I>(mut iter: I) -> Result<Commands, ()>
+/// Parses `timer [start|stop|reset]`, showing the timer if no action is given
+fn parse_timer<'a, I>(args: &mut Arguments<I>) -> Result<Commands, ParseError>
 where
     I: Iterator<Item = &'a str>,
 {
-    let arg_wheel = iter.next();
+    let action = match args.iter.next() {
+        None => TimerAction::Show,
+        Some(name) => {
+            args.pos += 1;
+            match name {
+                "start" => TimerAction::Start,
+                "stop" => TimerAction::Stop,
+                "reset" => TimerAction::Reset,
+                _ => return Err(ParseError::UnknownName(args.pos)),
+            }
+        }
+    };
 
-    if let Some(arg_wheel) = arg_wheel {
-        let wheel: i8 = arg_wheel.parse().map_err(|_| ())?;
+    Ok(Commands::Timer(action))
+}
 
-        Ok(Commands::Wheel(wheel))
-    } else {
-        Err(())
+/// Parses `cc <usage> [down|up|tap]`, tapping if no action is given
+fn parse_cc<'a, I>(args: &mut Arguments<I>) -> Result<Commands, ParseError>
+where
+    I: Iterator<Item = &'a str>,
+{
+    let arg_usage = args.next()?;
+    let usage: u16 = parse_usage(arg_usage, args.pos, CONSUMER_NAMES)?;
+    if usage > CONSUMER_USAGE_MAX {
+        return Err(ParseError::OutOfRange(args.pos));
+    }
+
+    match args.action()? {
+        Action::Down => Ok(Commands::ConsumerDown(usage)),
+        Action::Up => Ok(Commands::ConsumerUp(usage)),
+        Action::Tap => Ok(Commands::ConsumerTap(usage)),
+    }
+}
+
+/// Parses `sc <usage> [down|up|tap]`, tapping if no action is given
+fn parse_sc<'a, I>(args: &mut Arguments<I>) -> Result<Commands, ParseError>
+where
+    I: Iterator<Item = &'a str>,
+{
+    let arg_usage = args.next()?;
+    let usage: u8 = parse_usage(arg_usage, args.pos, SYSTEM_NAMES)?;
+    if !SYSTEM_NAMES.iter().any(|&(_, id)| id == usage) {
+        return Err(ParseError::OutOfRange(args.pos));
     }
+
+    match args.action()? {
+        Action::Down => Ok(Commands::SystemDown(usage)),
+        Action::Up => Ok(Commands::SystemUp(usage)),
+        Action::Tap => Ok(Commands::SystemTap(usage)),
+    }
+}
+
+/// Parses `cfg get [name]`, `cfg set <name> <value>`, `cfg save` or `cfg reset`
+fn parse_cfg<'a, I>(args: &mut Arguments<I>) -> Result<Commands, ParseError>
+where
+    I: Iterator<Item = &'a str>,
+{
+    let cmd = match args.next()? {
+        "get" => match args.iter.next() {
+            Some(name) => {
+                args.pos += 1;
+                let key = ConfigKey::from_name(name).ok_or(ParseError::UnknownName(args.pos))?;
+                ConfigCommand::Get(Some(key))
+            }
+            None => ConfigCommand::Get(None),
+        },
+        "set" => {
+            let name = args.next()?;
+            let key = ConfigKey::from_name(name).ok_or(ParseError::UnknownName(args.pos))?;
+            let arg = args.next()?;
+            let pos = args.pos;
+            let value = match key {
+                ConfigKey::Address => ConfigValue::Address(parse_hex(arg, pos)?),
+                ConfigKey::Channel => {
+                    let channel = parse_integer(arg, 10, pos)?;
+                    if channel > MAX_CHANNEL {
+                        return Err(ParseError::OutOfRange(pos));
+                    }
+                    ConfigValue::Channel(channel)
+                }
+                ConfigKey::Rate => {
+                    ConfigValue::Rate(Rate::from_name(arg).ok_or(ParseError::UnknownName(pos))?)
+                }
+                ConfigKey::Power => {
+                    let tx_power = parse_integer(arg, 10, pos)?;
+                    if tx_power > MAX_TX_POWER {
+                        return Err(ParseError::OutOfRange(pos));
+                    }
+                    ConfigValue::Power(tx_power)
+                }
+                ConfigKey::Crc => {
+                    ConfigValue::Crc(Crc::from_name(arg).ok_or(ParseError::UnknownName(pos))?)
+                }
+                ConfigKey::Retransmit => {
+                    let delay_us = parse_integer(arg, 10, pos)?;
+                    let count = args.number()?;
+                    if count > MAX_RETRANSMIT_COUNT {
+                        return Err(ParseError::OutOfRange(args.pos));
+                    }
+                    ConfigValue::Retransmit(
+                        Retransmit::new(delay_us, count).ok_or(ParseError::OutOfRange(pos))?,
+                    )
+                }
+                ConfigKey::Pipes => {
+                    let pipes = parse_integer(arg, 10, pos)?;
+                    if pipes < 1 || usize::from(pipes) > PIPE_COUNT {
+                        return Err(ParseError::OutOfRange(pos));
+                    }
+                    ConfigValue::Pipes(pipes)
+                }
+                ConfigKey::PipeAddress => ConfigValue::PipeAddress(parse_hex(arg, pos)?),
+                ConfigKey::FloorControl => ConfigValue::FloorControl(match arg {
+                    "on" => true,
+                    "off" => false,
+                    _ => return Err(ParseError::UnknownName(pos)),
+                }),
+                ConfigKey::Hopping => ConfigValue::Hopping(match arg {
+                    "on" => true,
+                    "off" => false,
+                    _ => return Err(ParseError::UnknownName(pos)),
+                }),
+                ConfigKey::ReleaseTimeout => {
+                    let ms = parse_integer(arg, 10, pos)?;
+                    // Presenters holding something down would be released
+                    // between their keep-alive packets
+                    if ms != 0 && ms <= KEEP_ALIVE_MS {
+                        return Err(ParseError::OutOfRange(pos));
+                    }
+                    ConfigValue::ReleaseTimeout(ms)
+                }
+                ConfigKey::Key => ConfigValue::Key(parse_hex(arg, pos)?),
+                ConfigKey::Log => ConfigValue::Log(
+                    config::log_level_from_name(arg).ok_or(ParseError::UnknownName(pos))?,
+                ),
+                ConfigKey::Layout => ConfigValue::Layout(
+                    LayoutId::from_name(arg).ok_or(ParseError::UnknownName(pos))?,
+                ),
+            };
+            ConfigCommand::Set(value)
+        }
+        "save" => ConfigCommand::Save,
+        "reset" => ConfigCommand::Reset,
+        _ => return Err(ParseError::UnknownName(args.pos)),
+    };
+
+    Ok(Commands::Config(cmd))
 }
@@ -0,0 +1,694 @@
+//! Settings kept in internal flash
+//!
+//! The config is stored as fixed-size records appended to the CONFIG region
+//! of memory.x, two flash pages used in turn. The valid record with the
+//! highest sequence number is the current one. When the page of the current
+//! record is full, the other page is erased and written from its start, so
+//! the current record survives a reset at any point.
+//!
+//! Record layout, multi-byte fields in little endian:
+//!
+//! | Offset | Size | Field                                  |
+//! |--------|------|----------------------------------------|
+//! | 0      | 4    | `RECORD_MAGIC`                         |
+//! | 4      | 2    | Payload version, `CONFIG_VERSION`      |
+//! | 6      | 2    | Payload length                         |
+//! | 8      | 4    | Sequence number                        |
+//! | 12     | 48   | Payload, see `Config::encode`          |
+//! | 60     | 4    | CRC-32 of the bytes before it          |
+
+use crate::crc::crc32;
+use crate::flash::{self, FlashError, PAGE_SIZE};
+use crate::layout::LayoutId;
+use crate::link::PIPE_COUNT;
+use crate::pairing::PairedDevice;
+use crate::secure::{Key, COUNTER_BLOCK, KEY_LEN};
+use crate::serial::BlockingSerialWriter;
+use core::fmt::Write;
+use embedded_nrf24l01::{CrcMode, DataRate};
+use log::LevelFilter;
+
+/// Version of the payload layout written by `Config::encode`
+pub const CONFIG_VERSION: u16 = 6;
+
+/// Start of the CONFIG region in memory.x
+const CONFIG_ADDR: u32 = 0x080F_F000;
+const PAGE_COUNT: u32 = 2;
+const RECORD_LEN: usize = 64;
+const RECORD_MAGIC: u32 = 0x4746_4E43; // "CNFG"
+const HEADER_LEN: usize = 12;
+const PAYLOAD_CAPACITY: usize = RECORD_LEN - HEADER_LEN - 4;
+const RECORDS_PER_PAGE: u32 = (PAGE_SIZE / RECORD_LEN) as u32;
+
+/// Address of the presenter on pipe 0 until one is paired
+const DEFAULT_ADDRESS: &[u8; 5] = b"\x2f\xa6\x37\x89\x73";
+const DEFAULT_CHANNEL: u8 = 82;
+/// Address of the presenter on pipe 1, see `Config::pipe_address`
+const DEFAULT_PIPE_ADDRESS: &[u8; 5] = b"\x51\x0c\xe4\x6b\x9d";
+/// Key of the presenter on pipe 0 until one is paired. It is in every
+/// build of the firmware, so it is no secret and only pairing secures the
+/// link.
+const DEFAULT_KEY: &Key = b"\x02\x1e\xa8\x70\x2a\x51\x12\xd7\x51\xb0\x24\x78\x82\x95\xff\x60";
+/// Highest nRF24 channel, 2525MHz
+pub const MAX_CHANNEL: u8 = 125;
+/// Highest nRF24 PA level, 0dBm
+pub const MAX_TX_POWER: u8 = 3;
+/// Auto-retransmit delay step, and the longest delay
+pub const RETRANSMIT_STEP_US: u16 = 250;
+pub const MAX_RETRANSMIT_DELAY_US: u16 = 4000;
+/// Most auto-retransmits of a packet
+pub const MAX_RETRANSMIT_COUNT: u8 = 15;
+
+/// nRF24 air data rate
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Rate {
+    R250Kbps,
+    R1Mbps,
+    R2Mbps,
+}
+
+impl Rate {
+    pub const ALL: [Rate; 3] = [Rate::R250Kbps, Rate::R1Mbps, Rate::R2Mbps];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Rate::R250Kbps => "250k",
+            Rate::R1Mbps => "1m",
+            Rate::R2Mbps => "2m",
+        }
+    }
+
+    /// Looks a rate up by its case-insensitive name
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|rate| rate.name().eq_ignore_ascii_case(name))
+    }
+
+    pub fn data_rate(self) -> DataRate {
+        match self {
+            Rate::R250Kbps => DataRate::R250Kbps,
+            Rate::R1Mbps => DataRate::R1Mbps,
+            Rate::R2Mbps => DataRate::R2Mbps,
+        }
+    }
+}
+
+/// nRF24 CRC length
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Crc {
+    Off,
+    OneByte,
+    TwoBytes,
+}
+
+impl Crc {
+    pub const ALL: [Crc; 3] = [Crc::Off, Crc::OneByte, Crc::TwoBytes];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Crc::Off => "off",
+            Crc::OneByte => "1",
+            Crc::TwoBytes => "2",
+        }
+    }
+
+    /// Looks a CRC length up by its case-insensitive name
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|crc| crc.name().eq_ignore_ascii_case(name))
+    }
+
+    pub fn crc_mode(self) -> CrcMode {
+        match self {
+            Crc::Off => CrcMode::Disabled,
+            Crc::OneByte => CrcMode::OneByte,
+            Crc::TwoBytes => CrcMode::TwoBytes,
+        }
+    }
+}
+
+/// Auto-retransmit of packets sent by the receiver, as in SETUP_RETR
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Retransmit {
+    /// Delay in steps of `RETRANSMIT_STEP_US`, minus one
+    pub delay: u8,
+    pub count: u8,
+}
+
+impl Retransmit {
+    /// `None` unless the delay is a whole number of steps
+    pub fn new(delay_us: u16, count: u8) -> Option<Self> {
+        if delay_us == 0
+            || delay_us > MAX_RETRANSMIT_DELAY_US
+            || delay_us % RETRANSMIT_STEP_US != 0
+            || count > MAX_RETRANSMIT_COUNT
+        {
+            return None;
+        }
+        Some(Self {
+            delay: (delay_us / RETRANSMIT_STEP_US - 1) as u8,
+            count,
+        })
+    }
+
+    pub fn delay_us(self) -> u16 {
+        (u16::from(self.delay) + 1) * RETRANSMIT_STEP_US
+    }
+}
+
+/// Log levels as accepted by `cfg set log`, in `LevelFilter` order
+const LOG_LEVELS: [(&str, LevelFilter); 6] = [
+    ("off", LevelFilter::Off),
+    ("error", LevelFilter::Error),
+    ("warn", LevelFilter::Warn),
+    ("info", LevelFilter::Info),
+    ("debug", LevelFilter::Debug),
+    ("trace", LevelFilter::Trace),
+];
+
+/// Looks a log level up by its case-insensitive name
+pub fn log_level_from_name(name: &str) -> Option<LevelFilter> {
+    LOG_LEVELS
+        .iter()
+        .find(|(level_name, _)| level_name.eq_ignore_ascii_case(name))
+        .map(|&(_, level)| level)
+}
+
+/// Settings which can be read or written with `cfg`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConfigKey {
+    Address,
+    Channel,
+    Rate,
+    Power,
+    Crc,
+    Retransmit,
+    Pipes,
+    PipeAddress,
+    FloorControl,
+    Hopping,
+    ReleaseTimeout,
+    Key,
+    Log,
+    Layout,
+}
+
+impl ConfigKey {
+    pub const ALL: [ConfigKey; 14] = [
+        ConfigKey::Address,
+        ConfigKey::Channel,
+        ConfigKey::Rate,
+        ConfigKey::Power,
+        ConfigKey::Crc,
+        ConfigKey::Retransmit,
+        ConfigKey::Pipes,
+        ConfigKey::PipeAddress,
+        ConfigKey::FloorControl,
+        ConfigKey::Hopping,
+        ConfigKey::ReleaseTimeout,
+        ConfigKey::Key,
+        ConfigKey::Log,
+        ConfigKey::Layout,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ConfigKey::Address => "address",
+            ConfigKey::Channel => "channel",
+            ConfigKey::Rate => "rate",
+            ConfigKey::Power => "power",
+            ConfigKey::Crc => "crc",
+            ConfigKey::Retransmit => "retr",
+            ConfigKey::Pipes => "pipes",
+            ConfigKey::PipeAddress => "pipeaddr",
+            ConfigKey::FloorControl => "floor",
+            ConfigKey::Hopping => "hop",
+            ConfigKey::ReleaseTimeout => "release",
+            ConfigKey::Key => "key",
+            ConfigKey::Log => "log",
+            ConfigKey::Layout => "layout",
+        }
+    }
+
+    /// Whether the setting belongs to the nRF24
+    pub fn is_radio(self) -> bool {
+        matches!(
+            self,
+            ConfigKey::Address
+                | ConfigKey::Channel
+                | ConfigKey::Rate
+                | ConfigKey::Power
+                | ConfigKey::Crc
+                | ConfigKey::Retransmit
+                | ConfigKey::Pipes
+                | ConfigKey::PipeAddress
+        )
+    }
+
+    /// Looks a setting up by its case-insensitive name
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|key| key.name().eq_ignore_ascii_case(name))
+    }
+}
+
+/// A new value for one setting
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConfigValue {
+    Address([u8; 5]),
+    Channel(u8),
+    Rate(Rate),
+    Power(u8),
+    Crc(Crc),
+    Retransmit(Retransmit),
+    Pipes(u8),
+    PipeAddress([u8; 5]),
+    FloorControl(bool),
+    Hopping(bool),
+    /// Milliseconds above `packet::KEEP_ALIVE_MS`, 0 for never
+    ReleaseTimeout(u16),
+    Key(Key),
+    Log(LevelFilter),
+    Layout(LayoutId),
+}
+
+impl ConfigValue {
+    pub fn key(self) -> ConfigKey {
+        match self {
+            ConfigValue::Address(_) => ConfigKey::Address,
+            ConfigValue::Channel(_) => ConfigKey::Channel,
+            ConfigValue::Rate(_) => ConfigKey::Rate,
+            ConfigValue::Power(_) => ConfigKey::Power,
+            ConfigValue::Crc(_) => ConfigKey::Crc,
+            ConfigValue::Retransmit(_) => ConfigKey::Retransmit,
+            ConfigValue::Pipes(_) => ConfigKey::Pipes,
+            ConfigValue::PipeAddress(_) => ConfigKey::PipeAddress,
+            ConfigValue::FloorControl(_) => ConfigKey::FloorControl,
+            ConfigValue::Hopping(_) => ConfigKey::Hopping,
+            ConfigValue::ReleaseTimeout(_) => ConfigKey::ReleaseTimeout,
+            ConfigValue::Key(_) => ConfigKey::Key,
+            ConfigValue::Log(_) => ConfigKey::Log,
+            ConfigValue::Layout(_) => ConfigKey::Layout,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConfigCommand {
+    /// Prints one setting, or all but the key
+    Get(Option<ConfigKey>),
+    Set(ConfigValue),
+    Save,
+    /// Goes back to the defaults, until the next `Save`
+    Reset,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Config {
+    /// Presenter on pipe 0
+    pub paired: PairedDevice,
+    pub channel: u8,
+    pub rate: Rate,
+    pub tx_power: u8,
+    pub crc: Crc,
+    pub retransmit: Retransmit,
+    /// Number of RX pipes enabled, from pipe 0
+    pub pipes: u8,
+    /// Address of pipe 1, see `Config::pipe_address`
+    pub pipe_address: [u8; 5],
+    /// Only one pipe at a time may send commands
+    pub floor_control: bool,
+    /// Move away from `channel` when it gets busy, see `hopping`
+    pub hopping: bool,
+    /// Silence of a presenter after which everything it holds down is
+    /// released, in milliseconds, 0 for never. Longer than
+    /// `packet::KEEP_ALIVE_MS`.
+    pub release_ms: u16,
+    /// Counter floor of each pipe, see `secure`
+    pub counter_floor: [u32; PIPE_COUNT],
+    pub log_level: LevelFilter,
+    pub layout: LayoutId,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            paired: PairedDevice {
+                address: *DEFAULT_ADDRESS,
+                key: *DEFAULT_KEY,
+            },
+            channel: DEFAULT_CHANNEL,
+            rate: Rate::R2Mbps,
+            tx_power: MAX_TX_POWER,
+            crc: Crc::OneByte,
+            // Reset value of SETUP_RETR
+            retransmit: Retransmit { delay: 0, count: 3 },
+            pipes: 1,
+            pipe_address: *DEFAULT_PIPE_ADDRESS,
+            floor_control: false,
+            hopping: false,
+            release_ms: 10_000,
+            counter_floor: [0; PIPE_COUNT],
+            log_level: LevelFilter::Trace,
+            layout: LayoutId::default(),
+        }
+    }
+}
+
+impl Config {
+    pub fn set(&mut self, value: ConfigValue) {
+        match value {
+            ConfigValue::Address(address) => self.paired.address = address,
+            ConfigValue::Channel(channel) => self.channel = channel,
+            ConfigValue::Rate(rate) => self.rate = rate,
+            ConfigValue::Power(tx_power) => self.tx_power = tx_power,
+            ConfigValue::Crc(crc) => self.crc = crc,
+            ConfigValue::Retransmit(retransmit) => self.retransmit = retransmit,
+            ConfigValue::Pipes(pipes) => self.pipes = pipes,
+            ConfigValue::PipeAddress(address) => self.pipe_address = address,
+            ConfigValue::FloorControl(on) => self.floor_control = on,
+            ConfigValue::Hopping(on) => self.hopping = on,
+            ConfigValue::ReleaseTimeout(ms) => self.release_ms = ms,
+            ConfigValue::Key(key) => self.paired.key = key,
+            ConfigValue::Log(log_level) => self.log_level = log_level,
+            ConfigValue::Layout(layout) => self.layout = layout,
+        }
+    }
+
+    /// Prints `CFG <name>=<value>` over serial
+    pub fn print(&self, key: Option<ConfigKey>) {
+        let mut writer = BlockingSerialWriter;
+        writer.write_str("CFG").ok();
+        for &shown in ConfigKey::ALL.iter() {
+            // The key is only shown when asked for
+            if key.map_or(shown == ConfigKey::Key, |key| key != shown) {
+                continue;
+            }
+            write!(writer, " {}=", shown.name()).ok();
+            match shown {
+                ConfigKey::Address => write_hex(&mut writer, &self.paired.address),
+                ConfigKey::Channel => write!(writer, "{}", self.channel).ok(),
+                ConfigKey::Rate => writer.write_str(self.rate.name()).ok(),
+                ConfigKey::Power => write!(writer, "{}", self.tx_power).ok(),
+                ConfigKey::Crc => writer.write_str(self.crc.name()).ok(),
+                ConfigKey::Retransmit => write!(
+                    writer,
+                    "{}/{}",
+                    self.retransmit.delay_us(),
+                    self.retransmit.count
+                )
+                .ok(),
+                ConfigKey::Pipes => write!(writer, "{}", self.pipes).ok(),
+                ConfigKey::PipeAddress => write_hex(&mut writer, &self.pipe_address),
+                ConfigKey::FloorControl => writer
+                    .write_str(if self.floor_control { "on" } else { "off" })
+                    .ok(),
+                ConfigKey::Hopping => writer
+                    .write_str(if self.hopping { "on" } else { "off" })
+                    .ok(),
+                ConfigKey::ReleaseTimeout => write!(writer, "{}", self.release_ms).ok(),
+                ConfigKey::Key => write_hex(&mut writer, &self.paired.key),
+                ConfigKey::Log => writer.write_str(LOG_LEVELS[self.log_level as usize].0).ok(),
+                ConfigKey::Layout => writer.write_str(self.layout.layout().name()).ok(),
+            };
+        }
+        writer.write_str("\r\n").ok();
+    }
+
+    /// Address of an RX pipe
+    ///
+    /// Pipes 2 to 5 share all but the first byte with pipe 1, so they count
+    /// up from the first byte of `pipe_address`.
+    pub fn pipe_address(&self, pipe: u8) -> [u8; 5] {
+        match pipe {
+            0 => self.paired.address,
+            _ => {
+                let mut address = self.pipe_address;
+                address[0] = address[0].wrapping_add(pipe - 1);
+                address
+            }
+        }
+    }
+
+    /// Payload layout of `CONFIG_VERSION` 6: address, key, then channel,
+    /// rate, TX power, log level, layout, CRC, SETUP_RETR and the number of
+    /// pipes as one byte each, the pipe 1 address, the floor control flag,
+    /// the hopping flag, the release timeout and the counter floors. Each
+    /// floor takes 12 bits in `COUNTER_BLOCK`s, two pipes in three bytes.
+    /// Version 1 ends after the layout, version 2 after SETUP_RETR, version 3
+    /// after the floor control flag, version 4 after the hopping flag and
+    /// version 5 after the release timeout.
+    fn encode(&self, payload: &mut [u8; PAYLOAD_CAPACITY]) -> usize {
+        payload[..5].copy_from_slice(&self.paired.address);
+        payload[5..21].copy_from_slice(&self.paired.key);
+        payload[21] = self.channel;
+        payload[22] = self.rate as u8;
+        payload[23] = self.tx_power;
+        payload[24] = self.log_level as u8;
+        payload[25] = self.layout as u8;
+        payload[26] = self.crc as u8;
+        payload[27] = self.retransmit.delay << 4 | self.retransmit.count;
+        payload[28] = self.pipes;
+        payload[29..34].copy_from_slice(&self.pipe_address);
+        payload[34] = self.floor_control as u8;
+        payload[35] = self.hopping as u8;
+        payload[36..38].copy_from_slice(&self.release_ms.to_le_bytes());
+        for (bytes, floors) in payload[38..47]
+            .chunks_mut(3)
+            .zip(self.counter_floor.chunks(2))
+        {
+            let pair = (floors[0] / COUNTER_BLOCK) | (floors[1] / COUNTER_BLOCK) << 12;
+            bytes.copy_from_slice(&pair.to_le_bytes()[..3]);
+        }
+        47
+    }
+
+    fn decode(version: u16, payload: &[u8]) -> Option<Self> {
+        let len = match version {
+            1 => 26,
+            2 => 28,
+            3 => 35,
+            4 => 36,
+            5 => 38,
+            6 => 47,
+            _ => return None,
+        };
+        if payload.len() != len {
+            return None;
+        }
+
+        let mut address = [0u8; 5];
+        address.copy_from_slice(&payload[..5]);
+        let mut key = [0u8; KEY_LEN];
+        key.copy_from_slice(&payload[5..21]);
+        let channel = payload[21];
+        let tx_power = payload[23];
+        if channel > MAX_CHANNEL || tx_power > MAX_TX_POWER {
+            return None;
+        }
+        Some(Self {
+            paired: PairedDevice { address, key },
+            channel,
+            rate: *Rate::ALL.get(usize::from(payload[22]))?,
+            tx_power,
+            log_level: LOG_LEVELS.get(usize::from(payload[24]))?.1,
+            layout: *LayoutId::ALL.get(usize::from(payload[25]))?,
+            crc: match payload.get(26) {
+                Some(&crc) => *Crc::ALL.get(usize::from(crc))?,
+                None => Self::default().crc,
+            },
+            retransmit: match payload.get(27) {
+                Some(&setup_retr) => Retransmit {
+                    delay: setup_retr >> 4,
+                    count: setup_retr & 0x0F,
+                },
+                None => Self::default().retransmit,
+            },
+            pipes: match payload.get(28) {
+                Some(&pipes) if pipes >= 1 && usize::from(pipes) <= PIPE_COUNT => pipes,
+                Some(_) => return None,
+                None => Self::default().pipes,
+            },
+            pipe_address: match payload.get(29..34) {
+                Some(bytes) => {
+                    let mut address = [0u8; 5];
+                    address.copy_from_slice(bytes);
+                    address
+                }
+                None => Self::default().pipe_address,
+            },
+            floor_control: matches!(payload.get(34), Some(&on) if on != 0),
+            hopping: matches!(payload.get(35), Some(&on) if on != 0),
+            release_ms: match payload.get(36..38) {
+                Some(bytes) => u16::from_le_bytes([bytes[0], bytes[1]]),
+                None => Self::default().release_ms,
+            },
+            counter_floor: match payload.get(38..47) {
+                Some(bytes) => {
+                    let mut floors = [0; PIPE_COUNT];
+                    for (floors, bytes) in floors.chunks_mut(2).zip(bytes.chunks(3)) {
+                        let pair = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], 0]);
+                        floors[0] = (pair & 0xFFF) * COUNTER_BLOCK;
+                        floors[1] = (pair >> 12) * COUNTER_BLOCK;
+                    }
+                    floors
+                }
+                None => Self::default().counter_floor,
+            },
+        })
+    }
+
+    /// Reads the current record, falling back to the defaults
+    pub fn load() -> Self {
+        match find_current() {
+            Some((_, record)) => {
+                let version = u16::from_le_bytes([record[4], record[5]]);
+                let len = usize::from(u16::from_le_bytes([record[6], record[7]]));
+                let payload = &record[HEADER_LEN..HEADER_LEN + len];
+                Self::decode(version, payload).unwrap_or_else(|| {
+                    warn!("Config version {} not supported, using defaults", version);
+                    Self::default()
+                })
+            }
+            None => {
+                warn!("No valid config stored, using defaults");
+                Self::default()
+            }
+        }
+    }
+
+    /// Saves the counter floors into the stored record, leaving the other
+    /// settings as they were saved
+    ///
+    /// Floors of a key which isn't saved mean nothing after a reset, so
+    /// they are not saved either.
+    pub fn save_counter_floors(&self) -> Result<(), FlashError> {
+        let mut saved = Self::load();
+        if saved.paired.key != self.paired.key {
+            return Ok(());
+        }
+        saved.counter_floor = self.counter_floor;
+        saved.save()
+    }
+
+    /// Appends a record, erasing the other page when the current one is full
+    pub fn save(&self) -> Result<(), FlashError> {
+        let (addr, seq) = match find_current() {
+            Some((current, record)) => {
+                let page = page_of(current);
+                let next = (current + RECORD_LEN as u32..page + PAGE_SIZE as u32)
+                    .step_by(RECORD_LEN)
+                    .find(|&addr| is_erased(addr));
+                let addr = match next {
+                    Some(addr) => addr,
+                    None => {
+                        let other = other_page(page);
+                        flash::erase_page(other)?;
+                        other
+                    }
+                };
+                (addr, record_seq(&record).wrapping_add(1))
+            }
+            None => {
+                // Nothing valid, so both pages hold only garbage
+                flash::erase_page(CONFIG_ADDR)?;
+                (CONFIG_ADDR, 0)
+            }
+        };
+
+        let mut record = [0u8; RECORD_LEN];
+        let mut payload = [0u8; PAYLOAD_CAPACITY];
+        let len = self.encode(&mut payload);
+        record[..4].copy_from_slice(&RECORD_MAGIC.to_le_bytes());
+        record[4..6].copy_from_slice(&CONFIG_VERSION.to_le_bytes());
+        record[6..8].copy_from_slice(&(len as u16).to_le_bytes());
+        record[8..12].copy_from_slice(&seq.to_le_bytes());
+        record[HEADER_LEN..HEADER_LEN + PAYLOAD_CAPACITY].copy_from_slice(&payload);
+        let crc = crc32(&record[..RECORD_LEN - 4]);
+        record[RECORD_LEN - 4..].copy_from_slice(&crc.to_le_bytes());
+
+        let mut dwords = [0u64; RECORD_LEN / 8];
+        for (dword, bytes) in dwords.iter_mut().zip(record.chunks(8)) {
+            let mut buf = [0u8; 8];
+            buf.copy_from_slice(bytes);
+            *dword = u64::from_le_bytes(buf);
+        }
+        flash::program(addr, &dwords)?;
+        info!("Config saved, record {}", seq);
+        Ok(())
+    }
+}
+
+pub fn write_hex(writer: &mut BlockingSerialWriter, bytes: &[u8]) -> Option<()> {
+    for byte in bytes {
+        write!(writer, "{:02x}", byte).ok()?;
+    }
+    Some(())
+}
+
+fn page_of(addr: u32) -> u32 {
+    addr - (addr - CONFIG_ADDR) % PAGE_SIZE as u32
+}
+
+fn other_page(page: u32) -> u32 {
+    if page == CONFIG_ADDR {
+        CONFIG_ADDR + PAGE_SIZE as u32
+    } else {
+        CONFIG_ADDR
+    }
+}
+
+/// Reads the record slot at `addr`, or `None` when flash ECC found it
+/// broken, e.g. by a power loss while it was written
+fn read_slot(addr: u32) -> Option<[u8; RECORD_LEN]> {
+    let mut record = [0u8; RECORD_LEN];
+    match flash::read(addr, &mut record) {
+        Ok(()) => Some(record),
+        Err(e) => {
+            warn!("Config slot {:#010x} unreadable: {:?}", addr, e);
+            None
+        }
+    }
+}
+
+/// Whether the slot at `addr` can be written. A broken slot can't.
+fn is_erased(addr: u32) -> bool {
+    read_slot(addr).map_or(false, |record| record.iter().all(|&b| b == 0xFF))
+}
+
+/// Whether the record is complete and not corrupted
+fn is_valid(record: &[u8; RECORD_LEN]) -> bool {
+    let magic = u32::from_le_bytes([record[0], record[1], record[2], record[3]]);
+    let len = usize::from(u16::from_le_bytes([record[6], record[7]]));
+    let crc = u32::from_le_bytes([record[60], record[61], record[62], record[63]]);
+    magic == RECORD_MAGIC && len <= PAYLOAD_CAPACITY && crc == crc32(&record[..RECORD_LEN - 4])
+}
+
+fn record_seq(record: &[u8; RECORD_LEN]) -> u32 {
+    u32::from_le_bytes([record[8], record[9], record[10], record[11]])
+}
+
+/// Finds the valid record with the highest sequence number, with its
+/// address. Broken slots are skipped.
+fn find_current() -> Option<(u32, [u8; RECORD_LEN])> {
+    let mut current: Option<(u32, [u8; RECORD_LEN])> = None;
+    for slot in 0..PAGE_COUNT * RECORDS_PER_PAGE {
+        let addr = CONFIG_ADDR + slot * RECORD_LEN as u32;
+        let record = match read_slot(addr) {
+            Some(record) if is_valid(&record) => record,
+            _ => continue,
+        };
+        if current.map_or(true, |(_, current)| {
+            record_seq(&record) > record_seq(&current)
+        }) {
+            current = Some((addr, record));
+        }
+    }
+    current
+}
@@ -0,0 +1,149 @@
+//! Erasing and programming the internal flash
+//!
+//! Only pages of bank 2 are written, so the code running from bank 1 keeps
+//! going while an operation is in progress.
+//!
+//! A write cut short by a power loss can leave a double word whose ECC can't
+//! correct it. Reading it raises an NMI, which `handle_nmi` takes and `read`
+//! turns into an error, so a broken record doesn't stop the firmware.
+
+use core::ptr;
+use core::sync::atomic::{AtomicBool, Ordering};
+use stm32l4xx_hal::stm32::{flash, FLASH};
+
+pub const PAGE_SIZE: usize = 2048;
+const BANK2_START: u32 = 0x0808_0000;
+const BANK2_END: u32 = 0x0810_0000;
+
+const KEY1: u32 = 0x4567_0123;
+const KEY2: u32 = 0xCDEF_89AB;
+/// OPTVERR, RDERR, FASTERR, MISERR, PGSERR, SIZERR, PGAERR, WRPERR, PROGERR
+/// and OPERR of FLASH_SR
+const SR_ERRORS: u32 = 0xC3FA;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FlashError {
+    /// The address is not in bank 2 or not aligned
+    InvalidAddress(u32),
+    /// Error bits of FLASH_SR
+    Operation(u32),
+    /// A double ECC error while reading the data at the address
+    Ecc(u32),
+}
+
+/// Set by `handle_nmi` for a double ECC error
+static ECC_ERROR: AtomicBool = AtomicBool::new(false);
+
+/// Unlocks FLASH_CR until dropped
+struct Unlocked {
+    flash: &'static flash::RegisterBlock,
+}
+
+impl Unlocked {
+    fn new() -> Self {
+        let flash = unsafe { &(*FLASH::ptr()) };
+        if flash.cr.read().lock().bit_is_set() {
+            flash.keyr.write(|w| unsafe { w.bits(KEY1) });
+            flash.keyr.write(|w| unsafe { w.bits(KEY2) });
+        }
+        // Clear errors left by earlier operations
+        flash.sr.write(|w| unsafe { w.bits(SR_ERRORS) });
+        Self { flash }
+    }
+
+    fn wait(&self) -> Result<(), FlashError> {
+        while self.flash.sr.read().bsy().bit_is_set() {}
+        let errors = self.flash.sr.read().bits() & SR_ERRORS;
+        if errors != 0 {
+            self.flash.sr.write(|w| unsafe { w.bits(errors) });
+            return Err(FlashError::Operation(errors));
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Unlocked {
+    fn drop(&mut self) {
+        self.flash.cr.modify(|_, w| w.lock().set_bit());
+        // The data cache may hold what was read before the change
+        self.flash.acr.modify(|_, w| w.dcen().clear_bit());
+        self.flash.acr.modify(|_, w| w.dcrst().set_bit());
+        self.flash.acr.modify(|_, w| w.dcrst().clear_bit());
+        self.flash.acr.modify(|_, w| w.dcen().set_bit());
+    }
+}
+
+fn check_address(addr: u32, align: u32) -> Result<(), FlashError> {
+    if addr < BANK2_START || addr >= BANK2_END || addr % align != 0 {
+        return Err(FlashError::InvalidAddress(addr));
+    }
+    Ok(())
+}
+
+/// Erases the page starting at `addr`
+pub fn erase_page(addr: u32) -> Result<(), FlashError> {
+    check_address(addr, PAGE_SIZE as u32)?;
+    let page = ((addr - BANK2_START) / PAGE_SIZE as u32) as u8;
+
+    let unlocked = Unlocked::new();
+    unlocked
+        .flash
+        .cr
+        .modify(|_, w| unsafe { w.per().set_bit().bker().set_bit().pnb().bits(page) });
+    unlocked.flash.cr.modify(|_, w| w.strt().set_bit());
+    let result = unlocked.wait();
+    unlocked.flash.cr.modify(|_, w| w.per().clear_bit());
+    result
+}
+
+/// Programs erased flash at `addr` with double words
+pub fn program(addr: u32, data: &[u64]) -> Result<(), FlashError> {
+    check_address(addr, 8)?;
+    check_address(addr + (data.len() as u32 * 8) - 1, 1)?;
+
+    let unlocked = Unlocked::new();
+    unlocked.flash.cr.modify(|_, w| w.pg().set_bit());
+    let mut result = Ok(());
+    for (i, &dword) in data.iter().enumerate() {
+        let dst = (addr as usize + i * 8) as *mut u32;
+        // Safety: The address is checked to be in bank 2
+        unsafe {
+            ptr::write_volatile(dst, dword as u32);
+            ptr::write_volatile(dst.add(1), (dword >> 32) as u32);
+        }
+        result = unlocked.wait();
+        if result.is_err() {
+            break;
+        }
+    }
+    unlocked.flash.cr.modify(|_, w| w.pg().clear_bit());
+    result
+}
+
+/// Copies the flash at `addr` into `buf`
+pub fn read(addr: u32, buf: &mut [u8]) -> Result<(), FlashError> {
+    ECC_ERROR.store(false, Ordering::SeqCst);
+    for (i, byte) in buf.iter_mut().enumerate() {
+        // Safety: Flash is always mapped and only changed through this module
+        *byte = unsafe { ptr::read_volatile((addr as usize + i) as *const u8) };
+    }
+    if ECC_ERROR.swap(false, Ordering::SeqCst) {
+        return Err(FlashError::Ecc(addr));
+    }
+    Ok(())
+}
+
+/// Takes the NMI of a double ECC error, returning false for other NMIs
+///
+/// The read which failed goes on with bad data, so `read` reports it.
+pub fn handle_nmi() -> bool {
+    let flash = unsafe { &(*FLASH::ptr()) };
+    let eccr = flash.eccr.read();
+    if eccr.eccd().bit_is_clear() {
+        return false;
+    }
+    // ECCD is cleared by writing 1
+    flash.eccr.modify(|_, w| w.eccd().set_bit());
+    ECC_ERROR.store(true, Ordering::SeqCst);
+    true
+}
@@ -0,0 +1,15 @@
+use super::control::CONSUMER_REPORT_ID;
+
+/// Consumer Page (0x0C) usage currently pressed, 0 if none
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ConsumerReport {
+    pub usage_id: u16,
+}
+
+impl ConsumerReport {
+    /// Serializes the report, prefixed with its report ID
+    pub fn to_bytes(&self) -> [u8; 3] {
+        let usage_id = self.usage_id.to_le_bytes();
+        [CONSUMER_REPORT_ID, usage_id[0], usage_id[1]]
+    }
+}
@@ -0,0 +1,34 @@
+//! Report descriptor of the control interface
+//!
+//! Holds the non-keyboard controls, told apart by their report IDs.
+
+pub const CONSUMER_REPORT_ID: u8 = 1;
+pub const SYSTEM_REPORT_ID: u8 = 2;
+
+#[rustfmt::skip]
+pub const CONTROL_DESC: &[u8] = &[
+    0x05, 0x0C,                     // Usage Page (Consumer)
+    0x09, 0x01,                     // Usage (Consumer Control)
+    0xA1, 0x01,                     // Collection (Application)
+    0x85, CONSUMER_REPORT_ID,       //   Report ID
+    0x15, 0x00,                     //   Logical Minimum (0)
+    0x26, 0xFF, 0x03,               //   Logical Maximum (0x3FF)
+    0x19, 0x00,                     //   Usage Minimum (0)
+    0x2A, 0xFF, 0x03,               //   Usage Maximum (0x3FF)
+    0x75, 0x10,                     //   Report Size (16)
+    0x95, 0x01,                     //   Report Count (1)
+    0x81, 0x00,                     //   Input (Data, Array, Absolute)
+    0xC0,                           // End Collection
+    0x05, 0x01,                     // Usage Page (Generic Desktop)
+    0x09, 0x80,                     // Usage (System Control)
+    0xA1, 0x01,                     // Collection (Application)
+    0x85, SYSTEM_REPORT_ID,         //   Report ID
+    0x16, 0x81, 0x00,               //   Logical Minimum (0x81)
+    0x26, 0x83, 0x00,               //   Logical Maximum (0x83)
+    0x19, 0x81,                     //   Usage Minimum (System Power Down)
+    0x29, 0x83,                     //   Usage Maximum (System Wake Up)
+    0x75, 0x08,                     //   Report Size (8)
+    0x95, 0x01,                     //   Report Count (1)
+    0x81, 0x00,                     //   Input (Data, Array, Absolute)
+    0xC0,                           // End Collection
+];
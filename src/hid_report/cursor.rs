@@ -1,24 +1,7 @@
+use super::pointer::CURSOR_REPORT_ID;
 use super::MouseButtons;
-use usbd_hid::descriptor::{gen_hid_descriptor, generator_prelude::*};
 
-#[gen_hid_descriptor(
-    (collection = APPLICATION, usage_page = GENERIC_DESKTOP, usage = MOUSE) = {
-        (collection = PHYSICAL, usage = POINTER) = {
-            (usage_page = BUTTON, usage_min = BUTTON_1, usage_max = BUTTON_3) = {
-                #[packed_bits 3] #[item_settings data,variable,absolute] buttons=input;
-            };
-            (usage_page = GENERIC_DESKTOP,) = {
-                (usage = X,) = {
-                    #[item_settings data,variable,absolute] x=input;
-                };
-                (usage = Y,) = {
-                    #[item_settings data,variable,absolute] y=input;
-                };
-            };
-        };
-    }
-)]
-#[derive(Default)]
+#[derive(Clone, Copy, Debug, Default)]
 pub struct CursorReport {
     pub buttons: u8,
     pub x: u16,
@@ -41,4 +24,11 @@ impl CursorReport {
             ..Self::default()
         }
     }
+
+    /// Serializes the report, prefixed with its report ID
+    pub fn to_bytes(&self) -> [u8; 6] {
+        let x = self.x.to_le_bytes();
+        let y = self.y.to_le_bytes();
+        [CURSOR_REPORT_ID, self.buttons, x[0], x[1], y[0], y[1]]
+    }
 }
@@ -33,6 +33,18 @@ bitflags! {
     }
 }
 
+bitflags! {
+    /// Bits of the LED output report
+    #[derive(Default)]
+    pub struct KeyboardLeds: u8 {
+        const NUM_LOCK = 0b00000001;
+        const CAPS_LOCK = 0b00000010;
+        const SCROLL_LOCK = 0b00000100;
+        const COMPOSE = 0b00001000;
+        const KANA = 0b00010000;
+    }
+}
+
 impl KeyboardModifiers {
     pub const fn is_modifier(code: u8) -> bool {
         (code >= 0xE0) && (code <= 0xE7)
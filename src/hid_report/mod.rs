@@ -1,7 +1,15 @@
+mod consumer;
+mod control;
 mod cursor;
 mod keyboard;
 mod mouse;
+mod pointer;
+mod system;
 
+pub use consumer::ConsumerReport;
+pub use control::CONTROL_DESC;
 pub use cursor::CursorReport;
-pub use keyboard::{KeyboardModifiers, KeyboardReport};
+pub use keyboard::{KeyboardLeds, KeyboardModifiers, KeyboardReport};
 pub use mouse::{MouseButtons, MouseReport};
+pub use pointer::POINTER_DESC;
+pub use system::SystemReport;
@@ -1,26 +1,6 @@
-use usbd_hid::descriptor::{gen_hid_descriptor, generator_prelude::*};
+use super::pointer::MOUSE_REPORT_ID;
 
-#[gen_hid_descriptor(
-    (collection = APPLICATION, usage_page = GENERIC_DESKTOP, usage = MOUSE) = {
-        (collection = PHYSICAL, usage = POINTER) = {
-            (usage_page = BUTTON, usage_min = BUTTON_1, usage_max = BUTTON_3) = {
-                #[packed_bits 3] #[item_settings data,variable,absolute] buttons=input;
-            };
-            (usage_page = GENERIC_DESKTOP,) = {
-                (usage = X,) = {
-                    #[item_settings data,variable,relative] x=input;
-                };
-                (usage = Y,) = {
-                    #[item_settings data,variable,relative] y=input;
-                };
-                (usage = 0x38,) = {
-                    #[item_settings data,variable,relative] wheel=input;
-                };
-            };
-        };
-    }
-)]
-#[derive(Default)]
+#[derive(Clone, Copy, Debug, Default)]
 pub struct MouseReport {
     pub buttons: u8,
     pub x: i16,
@@ -51,6 +31,21 @@ impl MouseReport {
             ..Self::default()
         }
     }
+
+    /// Serializes the report, prefixed with its report ID
+    pub fn to_bytes(&self) -> [u8; 7] {
+        let x = self.x.to_le_bytes();
+        let y = self.y.to_le_bytes();
+        [
+            MOUSE_REPORT_ID,
+            self.buttons,
+            x[0],
+            x[1],
+            y[0],
+            y[1],
+            self.wheel as u8,
+        ]
+    }
 }
 
 bitflags! {
@@ -0,0 +1,71 @@
+//! Report descriptor of the pointer interface
+//!
+//! The relative mouse and the absolute cursor share one interface, told apart
+//! by their report IDs, since the OTG_FS peripheral only has 6 IN endpoints.
+
+pub const MOUSE_REPORT_ID: u8 = 1;
+pub const CURSOR_REPORT_ID: u8 = 2;
+
+#[rustfmt::skip]
+pub const POINTER_DESC: &[u8] = &[
+    0x05, 0x01,                     // Usage Page (Generic Desktop)
+    0x09, 0x02,                     // Usage (Mouse)
+    0xA1, 0x01,                     // Collection (Application)
+    0x85, MOUSE_REPORT_ID,          //   Report ID
+    0x09, 0x01,                     //   Usage (Pointer)
+    0xA1, 0x00,                     //   Collection (Physical)
+    0x05, 0x09,                     //     Usage Page (Button)
+    0x19, 0x01,                     //     Usage Minimum (Button 1)
+    0x29, 0x03,                     //     Usage Maximum (Button 3)
+    0x15, 0x00,                     //     Logical Minimum (0)
+    0x25, 0x01,                     //     Logical Maximum (1)
+    0x75, 0x01,                     //     Report Size (1)
+    0x95, 0x03,                     //     Report Count (3)
+    0x81, 0x02,                     //     Input (Data, Variable, Absolute)
+    0x75, 0x05,                     //     Report Size (5)
+    0x95, 0x01,                     //     Report Count (1)
+    0x81, 0x03,                     //     Input (Constant)
+    0x05, 0x01,                     //     Usage Page (Generic Desktop)
+    0x09, 0x30,                     //     Usage (X)
+    0x09, 0x31,                     //     Usage (Y)
+    0x16, 0x00, 0x80,               //     Logical Minimum (-32768)
+    0x26, 0xFF, 0x7F,               //     Logical Maximum (32767)
+    0x75, 0x10,                     //     Report Size (16)
+    0x95, 0x02,                     //     Report Count (2)
+    0x81, 0x06,                     //     Input (Data, Variable, Relative)
+    0x09, 0x38,                     //     Usage (Wheel)
+    0x15, 0x81,                     //     Logical Minimum (-127)
+    0x25, 0x7F,                     //     Logical Maximum (127)
+    0x75, 0x08,                     //     Report Size (8)
+    0x95, 0x01,                     //     Report Count (1)
+    0x81, 0x06,                     //     Input (Data, Variable, Relative)
+    0xC0,                           //   End Collection
+    0xC0,                           // End Collection
+    0x05, 0x01,                     // Usage Page (Generic Desktop)
+    0x09, 0x02,                     // Usage (Mouse)
+    0xA1, 0x01,                     // Collection (Application)
+    0x85, CURSOR_REPORT_ID,         //   Report ID
+    0x09, 0x01,                     //   Usage (Pointer)
+    0xA1, 0x00,                     //   Collection (Physical)
+    0x05, 0x09,                     //     Usage Page (Button)
+    0x19, 0x01,                     //     Usage Minimum (Button 1)
+    0x29, 0x03,                     //     Usage Maximum (Button 3)
+    0x15, 0x00,                     //     Logical Minimum (0)
+    0x25, 0x01,                     //     Logical Maximum (1)
+    0x75, 0x01,                     //     Report Size (1)
+    0x95, 0x03,                     //     Report Count (3)
+    0x81, 0x02,                     //     Input (Data, Variable, Absolute)
+    0x75, 0x05,                     //     Report Size (5)
+    0x95, 0x01,                     //     Report Count (1)
+    0x81, 0x03,                     //     Input (Constant)
+    0x05, 0x01,                     //     Usage Page (Generic Desktop)
+    0x09, 0x30,                     //     Usage (X)
+    0x09, 0x31,                     //     Usage (Y)
+    0x15, 0x00,                     //     Logical Minimum (0)
+    0x27, 0xFF, 0xFF, 0x00, 0x00,   //     Logical Maximum (65535)
+    0x75, 0x10,                     //     Report Size (16)
+    0x95, 0x02,                     //     Report Count (2)
+    0x81, 0x02,                     //     Input (Data, Variable, Absolute)
+    0xC0,                           //   End Collection
+    0xC0,                           // End Collection
+];
@@ -0,0 +1,14 @@
+use super::control::SYSTEM_REPORT_ID;
+
+/// Generic Desktop System Control usage currently pressed, 0 if none
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemReport {
+    pub usage_id: u8,
+}
+
+impl SystemReport {
+    /// Serializes the report, prefixed with its report ID
+    pub fn to_bytes(&self) -> [u8; 2] {
+        [SYSTEM_REPORT_ID, self.usage_id]
+    }
+}
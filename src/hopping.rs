@@ -0,0 +1,276 @@
+//! Moving the link away from busy channels
+//!
+//! The receiver starts on the configured channel, the home channel. When a
+//! window of `LINK_WINDOW` loses too many packets, it measures every channel
+//! with RPD, one step per call to `Hopper::poll`, and picks the quietest one.
+//! A step either tunes the radio to a channel or takes one reading, so the
+//! millisecond between two ticks gives the PLL and RPD the 170us they need
+//! without waiting while the radio is locked.
+//!
+//! The new channel is announced in the status ACK payloads, and every
+//! presenter seeing it answers with an `Opcode::HopConfirm` frame. The
+//! receiver moves once each pipe heard within `ACTIVE_MS` has confirmed the
+//! channel. If they don't all confirm it within `ANNOUNCE_MS`, it stays where
+//! it is.
+//!
+//! Presenters keep the last channel they confirmed and move to it once their
+//! packets stop being acknowledged. If nothing arrives on the new channel
+//! for `SILENCE_MS`, both go back to the home channel.
+
+use crate::clock::Instant;
+use crate::config::Config;
+use crate::link::{LinkStats, PIPE_COUNT};
+use crate::nrf24_mode::{reg, NRF24Device, NRF24Mode};
+use embedded_nrf24l01::Configuration;
+
+/// Channels scanned, inside the 2.400-2.4835 GHz band with room for 2 Mbps
+const FIRST_CHANNEL: u8 = 2;
+const LAST_CHANNEL: u8 = 80;
+const SCAN_LEN: usize = (LAST_CHANNEL - FIRST_CHANNEL + 1) as usize;
+/// RPD readings taken on each channel, one per step after tuning to it
+const SCAN_SAMPLES: u8 = 8;
+/// Channels this close to the busy one are not picked
+const MIN_DISTANCE: i16 = 3;
+
+/// Share of lost packets in a window which starts a scan
+const LOSS_PERCENT: u32 = 25;
+/// Windows left alone after a move, whose loss comes from the move itself
+const SETTLE_WINDOWS: u8 = 2;
+/// How long the new channel is announced for the pipes to confirm it
+const ANNOUNCE_MS: u32 = 3_000;
+/// Pipes heard within this long have to confirm a new channel
+const ACTIVE_MS: u32 = 10_000;
+/// Silence on a new channel after which the link goes back home
+const SILENCE_MS: u32 = 30_000;
+
+#[derive(Debug)]
+enum State {
+    /// Watching the loss of each window
+    Listening,
+    /// Measuring `channel` and the ones after it, tuned at step 0 and read
+    /// at the steps after
+    Scanning { channel: u8, step: u8 },
+    /// Announcing `channel` since `since`, confirmed by the pipes in the
+    /// `confirmed` bits
+    Announcing {
+        channel: u8,
+        since: Instant,
+        confirmed: u8,
+    },
+}
+
+#[derive(Debug)]
+pub struct Hopper {
+    state: State,
+    /// Channel the radio listens on
+    channel: u8,
+    /// Busy RPD readings of each scanned channel
+    busy: [u8; SCAN_LEN],
+    last_packet: Instant,
+    /// When each pipe was last heard from
+    last_heard: [Option<Instant>; PIPE_COUNT],
+    settle_windows: u8,
+}
+
+impl Hopper {
+    pub fn new(config: &Config) -> Self {
+        Self {
+            state: State::Listening,
+            channel: config.channel,
+            busy: [0; SCAN_LEN],
+            last_packet: Instant::now(),
+            last_heard: [None; PIPE_COUNT],
+            settle_windows: 0,
+        }
+    }
+
+    /// Whether the radio is away scanning, so packets can't be read
+    pub fn is_scanning(&self) -> bool {
+        matches!(self.state, State::Scanning { .. })
+    }
+
+    /// Channel to put in the status ACK payloads
+    pub fn announced(&self) -> Option<u8> {
+        match self.state {
+            State::Announcing { channel, .. } => Some(channel),
+            _ => None,
+        }
+    }
+
+    /// Notes the pipes which had packets
+    pub fn received(&mut self, pipes: &[bool; PIPE_COUNT]) {
+        let now = Instant::now();
+        self.last_packet = now;
+        for (last_heard, _) in self
+            .last_heard
+            .iter_mut()
+            .zip(pipes)
+            .filter(|(_, &had)| had)
+        {
+            *last_heard = Some(now);
+        }
+    }
+
+    /// Notes a pipe confirming the announced channel
+    pub fn confirmed(&mut self, pipe: u8, confirmed_channel: u8) {
+        if let State::Announcing {
+            channel,
+            ref mut confirmed,
+            ..
+        } = self.state
+        {
+            if channel == confirmed_channel {
+                *confirmed |= 1 << pipe;
+            }
+        }
+    }
+
+    /// Starts a scan when the window which just closed lost too much
+    pub fn window_closed(&mut self, config: &Config, window: &LinkStats) {
+        if self.settle_windows > 0 {
+            self.settle_windows -= 1;
+            return;
+        }
+        if !config.hopping || !matches!(self.state, State::Listening) {
+            return;
+        }
+        if let Some(loss) = window.loss_percent() {
+            if loss >= LOSS_PERCENT {
+                info!("Channel {} lost {}%, scanning", self.channel, loss);
+                self.state = State::Scanning {
+                    channel: FIRST_CHANNEL,
+                    step: 0,
+                };
+            }
+        }
+    }
+
+    /// Takes the next step of a scan or move
+    pub fn poll(&mut self, nrf24l01: &mut NRF24Mode<NRF24Device>, config: &Config) {
+        if !self.is_scanning() && nrf24l01.read_register(reg::RF_CH) != self.channel {
+            // Radio settings were written again, which puts it back home
+            self.reset(config);
+        }
+        if !config.hopping
+            && (self.channel != config.channel || !matches!(self.state, State::Listening))
+        {
+            self.go_home(nrf24l01, config);
+        }
+
+        match self.state {
+            State::Listening => {
+                if self.channel != config.channel && self.last_packet.elapsed_ms() >= SILENCE_MS {
+                    warn!("Nothing on channel {}", self.channel);
+                    self.go_home(nrf24l01, config);
+                }
+            }
+            State::Scanning { channel, step } => {
+                let busy = &mut self.busy[usize::from(channel - FIRST_CHANNEL)];
+                if step == 0 {
+                    set_channel(nrf24l01, channel);
+                    *busy = 0;
+                } else if nrf24l01.read_register(reg::RPD) & 0x01 != 0 {
+                    *busy += 1;
+                }
+                if step < SCAN_SAMPLES {
+                    self.state = State::Scanning {
+                        channel,
+                        step: step + 1,
+                    };
+                    return;
+                }
+                if channel < LAST_CHANNEL {
+                    self.state = State::Scanning {
+                        channel: channel + 1,
+                        step: 0,
+                    };
+                    return;
+                }
+
+                set_channel(nrf24l01, self.channel);
+                self.state = match self.quietest() {
+                    Some(channel) => {
+                        info!("Announcing channel {}", channel);
+                        State::Announcing {
+                            channel,
+                            since: Instant::now(),
+                            confirmed: 0,
+                        }
+                    }
+                    None => State::Listening,
+                };
+                self.settle_windows = SETTLE_WINDOWS;
+            }
+            State::Announcing {
+                channel,
+                since,
+                confirmed,
+            } => {
+                let active = self.active_pipes();
+                if active != 0 && active & !confirmed == 0 {
+                    info!("Moving to channel {}", channel);
+                    self.move_to(nrf24l01, channel);
+                } else if since.elapsed_ms() >= ANNOUNCE_MS {
+                    warn!(
+                        "Channel {} not confirmed by pipes {:#04x}, staying",
+                        channel,
+                        active & !confirmed
+                    );
+                    self.state = State::Listening;
+                    self.settle_windows = SETTLE_WINDOWS;
+                }
+            }
+        }
+    }
+
+    /// Goes back to the home channel, e.g. before pairing
+    pub fn go_home(&mut self, nrf24l01: &mut NRF24Mode<NRF24Device>, config: &Config) {
+        if self.channel != config.channel {
+            info!("Back to channel {}", config.channel);
+        }
+        self.move_to(nrf24l01, config.channel);
+    }
+
+    fn move_to(&mut self, nrf24l01: &mut NRF24Mode<NRF24Device>, channel: u8) {
+        set_channel(nrf24l01, channel);
+        self.channel = channel;
+        self.state = State::Listening;
+        self.last_packet = Instant::now();
+        self.settle_windows = SETTLE_WINDOWS;
+    }
+
+    fn reset(&mut self, config: &Config) {
+        self.channel = config.channel;
+        self.state = State::Listening;
+        self.last_packet = Instant::now();
+    }
+
+    /// Bits of the pipes heard within `ACTIVE_MS`
+    fn active_pipes(&self) -> u8 {
+        self.last_heard
+            .iter()
+            .enumerate()
+            .filter(|(_, last_heard)| matches!(last_heard, Some(at) if at.elapsed_ms() < ACTIVE_MS))
+            .fold(0, |pipes, (pipe, _)| pipes | 1 << pipe)
+    }
+
+    /// Channel away from the current one with the least busy readings,
+    /// counting its neighbours too
+    fn quietest(&self) -> Option<u8> {
+        (FIRST_CHANNEL..=LAST_CHANNEL)
+            .filter(|&channel| (i16::from(channel) - i16::from(self.channel)).abs() >= MIN_DISTANCE)
+            .min_by_key(|&channel| {
+                let index = usize::from(channel - FIRST_CHANNEL);
+                let around = index.saturating_sub(1)..=(index + 1).min(SCAN_LEN - 1);
+                self.busy[around]
+                    .iter()
+                    .map(|&busy| u32::from(busy))
+                    .sum::<u32>()
+            })
+    }
+}
+
+fn set_channel(nrf24l01: &mut NRF24Mode<NRF24Device>, channel: u8) {
+    nrf24l01.to_standby().set_frequency(channel).ok();
+    nrf24l01.to_rx();
+}
@@ -0,0 +1,66 @@
+//! Lock LEDs set by the host
+//!
+//! The host sends the keyboard output report either on the interrupt OUT
+//! endpoint, which `OTG_FS` reads, or with a SET_REPORT request, which
+//! `SetReport` answers since `HIDClass` doesn't. Both go through `store`,
+//! and everything showing the LEDs reads them with `get`.
+
+use crate::hid_report::KeyboardLeds;
+use core::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+use usb_device::class_prelude::*;
+use usb_device::control::{Recipient, RequestType};
+
+/// HID class request carrying a report from the host
+const HID_REQ_SET_REPORT: u8 = 0x09;
+/// Report type in the high byte of wValue
+const HID_REPORT_TYPE_OUTPUT: u16 = 0x02;
+
+static LEDS: AtomicU8 = AtomicU8::new(0);
+static CHANGED: AtomicBool = AtomicBool::new(false);
+
+/// Records an output report of the keyboard interface
+pub fn store(report: u8) {
+    if LEDS.swap(report, Ordering::Relaxed) != report {
+        CHANGED.store(true, Ordering::Release);
+    }
+}
+
+/// LEDs as last set by the host
+pub fn get() -> KeyboardLeds {
+    KeyboardLeds::from_bits_truncate(LEDS.load(Ordering::Relaxed))
+}
+
+/// Returns the LEDs if the host has changed them since the last call
+pub fn take() -> Option<KeyboardLeds> {
+    if CHANGED.swap(false, Ordering::Acquire) {
+        Some(get())
+    } else {
+        None
+    }
+}
+
+/// Accepts SET_REPORT requests for output reports
+///
+/// Only the keyboard interface has an output report, so any one-byte
+/// output report is taken as its LEDs. Must be polled before the HID
+/// classes, which would otherwise stall the request.
+#[derive(Debug, Default)]
+pub struct SetReport;
+
+impl<B: UsbBus> UsbClass<B> for SetReport {
+    fn control_out(&mut self, xfer: ControlOut<B>) {
+        let req = xfer.request();
+        if req.request_type != RequestType::Class
+            || req.recipient != Recipient::Interface
+            || req.request != HID_REQ_SET_REPORT
+            || req.value >> 8 != HID_REPORT_TYPE_OUTPUT
+        {
+            return;
+        }
+
+        if let [report] = *xfer.data() {
+            store(report);
+            xfer.accept().ok();
+        }
+    }
+}
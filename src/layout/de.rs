@@ -0,0 +1,68 @@
+use super::{common_char_to_key, KeyboardLayout, ALTGR, NONE, SHIFT};
+use crate::hid_report::KeyboardModifiers;
+
+/// German (QWERTZ) layout
+///
+/// `^`, `´` and `` ` `` are dead keys and cannot be typed on their own.
+#[derive(Clone, Copy, Debug)]
+pub struct De;
+
+impl KeyboardLayout for De {
+    fn name(&self) -> &'static str {
+        "de"
+    }
+
+    fn char_to_key(&self, c: char) -> Option<(KeyboardModifiers, u8)> {
+        let key = match c {
+            'z' => (NONE, 0x1C),
+            'Z' => (SHIFT, 0x1C),
+            'y' => (NONE, 0x1D),
+            'Y' => (SHIFT, 0x1D),
+            '@' => (ALTGR, 0x14),
+            '€' => (ALTGR, 0x08),
+            'µ' => (ALTGR, 0x10),
+            '!' => (SHIFT, 0x1E),
+            '"' => (SHIFT, 0x1F),
+            '²' => (ALTGR, 0x1F),
+            '§' => (SHIFT, 0x20),
+            '³' => (ALTGR, 0x20),
+            '$' => (SHIFT, 0x21),
+            '%' => (SHIFT, 0x22),
+            '&' => (SHIFT, 0x23),
+            '/' => (SHIFT, 0x24),
+            '{' => (ALTGR, 0x24),
+            '(' => (SHIFT, 0x25),
+            '[' => (ALTGR, 0x25),
+            ')' => (SHIFT, 0x26),
+            ']' => (ALTGR, 0x26),
+            '=' => (SHIFT, 0x27),
+            '}' => (ALTGR, 0x27),
+            'ß' => (NONE, 0x2D),
+            '?' => (SHIFT, 0x2D),
+            '\\' => (ALTGR, 0x2D),
+            'ü' => (NONE, 0x2F),
+            'Ü' => (SHIFT, 0x2F),
+            '+' => (NONE, 0x30),
+            '*' => (SHIFT, 0x30),
+            '~' => (ALTGR, 0x30),
+            '#' => (NONE, 0x32),
+            '\'' => (SHIFT, 0x32),
+            'ö' => (NONE, 0x33),
+            'Ö' => (SHIFT, 0x33),
+            'ä' => (NONE, 0x34),
+            'Ä' => (SHIFT, 0x34),
+            '°' => (SHIFT, 0x35),
+            ',' => (NONE, 0x36),
+            ';' => (SHIFT, 0x36),
+            '.' => (NONE, 0x37),
+            ':' => (SHIFT, 0x37),
+            '-' => (NONE, 0x38),
+            '_' => (SHIFT, 0x38),
+            '<' => (NONE, 0x64),
+            '>' => (SHIFT, 0x64),
+            '|' => (ALTGR, 0x64),
+            _ => return common_char_to_key(c),
+        };
+        Some(key)
+    }
+}
@@ -0,0 +1,81 @@
+use super::{KeyboardLayout, ALTGR, NONE, SHIFT};
+use crate::hid_report::KeyboardModifiers;
+
+/// French (AZERTY) layout
+///
+/// Digits need Shift, and `^`, `¨`, `~` and `` ` `` are dead keys which cannot
+/// be typed on their own.
+#[derive(Clone, Copy, Debug)]
+pub struct Fr;
+
+impl KeyboardLayout for Fr {
+    fn name(&self) -> &'static str {
+        "fr"
+    }
+
+    fn char_to_key(&self, c: char) -> Option<(KeyboardModifiers, u8)> {
+        let key = match c {
+            'a' => (NONE, 0x14),
+            'A' => (SHIFT, 0x14),
+            'q' => (NONE, 0x04),
+            'Q' => (SHIFT, 0x04),
+            'z' => (NONE, 0x1A),
+            'Z' => (SHIFT, 0x1A),
+            'w' => (NONE, 0x1D),
+            'W' => (SHIFT, 0x1D),
+            'm' => (NONE, 0x33),
+            'M' => (SHIFT, 0x33),
+            'b'..='y' => (NONE, 0x04 + (c as u8 - b'a')),
+            'B'..='Y' => (SHIFT, 0x04 + (c as u8 - b'A')),
+            '€' => (ALTGR, 0x08),
+            '1'..='9' => (SHIFT, 0x1E + (c as u8 - b'1')),
+            '0' => (SHIFT, 0x27),
+            '&' => (NONE, 0x1E),
+            'é' => (NONE, 0x1F),
+            '"' => (NONE, 0x20),
+            '#' => (ALTGR, 0x20),
+            '\'' => (NONE, 0x21),
+            '{' => (ALTGR, 0x21),
+            '(' => (NONE, 0x22),
+            '[' => (ALTGR, 0x22),
+            '-' => (NONE, 0x23),
+            '|' => (ALTGR, 0x23),
+            'è' => (NONE, 0x24),
+            '_' => (NONE, 0x25),
+            '\\' => (ALTGR, 0x25),
+            'ç' => (NONE, 0x26),
+            '^' => (ALTGR, 0x26),
+            'à' => (NONE, 0x27),
+            '@' => (ALTGR, 0x27),
+            ')' => (NONE, 0x2D),
+            '°' => (SHIFT, 0x2D),
+            ']' => (ALTGR, 0x2D),
+            '=' => (NONE, 0x2E),
+            '+' => (SHIFT, 0x2E),
+            '}' => (ALTGR, 0x2E),
+            '$' => (NONE, 0x30),
+            '£' => (SHIFT, 0x30),
+            '¤' => (ALTGR, 0x30),
+            '*' => (NONE, 0x32),
+            'µ' => (SHIFT, 0x32),
+            'ù' => (NONE, 0x34),
+            '%' => (SHIFT, 0x34),
+            '²' => (NONE, 0x35),
+            ',' => (NONE, 0x10),
+            '?' => (SHIFT, 0x10),
+            ';' => (NONE, 0x36),
+            '.' => (SHIFT, 0x36),
+            ':' => (NONE, 0x37),
+            '/' => (SHIFT, 0x37),
+            '!' => (NONE, 0x38),
+            '§' => (SHIFT, 0x38),
+            '<' => (NONE, 0x64),
+            '>' => (SHIFT, 0x64),
+            '\n' => (NONE, 0x28),
+            '\t' => (NONE, 0x2B),
+            ' ' => (NONE, 0x2C),
+            _ => return None,
+        };
+        Some(key)
+    }
+}
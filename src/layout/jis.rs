@@ -0,0 +1,54 @@
+use super::{common_char_to_key, KeyboardLayout, NONE, SHIFT};
+use crate::hid_report::KeyboardModifiers;
+
+/// Japanese (JIS) layout
+///
+/// Only characters available in direct input mode are mapped.
+#[derive(Clone, Copy, Debug)]
+pub struct Jis;
+
+impl KeyboardLayout for Jis {
+    fn name(&self) -> &'static str {
+        "jis"
+    }
+
+    fn char_to_key(&self, c: char) -> Option<(KeyboardModifiers, u8)> {
+        let key = match c {
+            '!' => (SHIFT, 0x1E),
+            '"' => (SHIFT, 0x1F),
+            '#' => (SHIFT, 0x20),
+            '$' => (SHIFT, 0x21),
+            '%' => (SHIFT, 0x22),
+            '&' => (SHIFT, 0x23),
+            '\'' => (SHIFT, 0x24),
+            '(' => (SHIFT, 0x25),
+            ')' => (SHIFT, 0x26),
+            '-' => (NONE, 0x2D),
+            '=' => (SHIFT, 0x2D),
+            '^' => (NONE, 0x2E),
+            '~' => (SHIFT, 0x2E),
+            '@' => (NONE, 0x2F),
+            '`' => (SHIFT, 0x2F),
+            '[' => (NONE, 0x30),
+            '{' => (SHIFT, 0x30),
+            ']' => (NONE, 0x32),
+            '}' => (SHIFT, 0x32),
+            ';' => (NONE, 0x33),
+            '+' => (SHIFT, 0x33),
+            ':' => (NONE, 0x34),
+            '*' => (SHIFT, 0x34),
+            ',' => (NONE, 0x36),
+            '<' => (SHIFT, 0x36),
+            '.' => (NONE, 0x37),
+            '>' => (SHIFT, 0x37),
+            '/' => (NONE, 0x38),
+            '?' => (SHIFT, 0x38),
+            '\\' => (NONE, 0x87),
+            '_' => (SHIFT, 0x87),
+            '¥' => (NONE, 0x89),
+            '|' => (SHIFT, 0x89),
+            _ => return common_char_to_key(c),
+        };
+        Some(key)
+    }
+}
@@ -0,0 +1,90 @@
+mod de;
+mod fr;
+mod jis;
+mod uk;
+mod us;
+
+use crate::hid_report::KeyboardModifiers;
+
+pub use de::De;
+pub use fr::Fr;
+pub use jis::Jis;
+pub use uk::Uk;
+pub use us::Us;
+
+const NONE: KeyboardModifiers = KeyboardModifiers::empty();
+const SHIFT: KeyboardModifiers = KeyboardModifiers::L_SHIFT;
+const ALTGR: KeyboardModifiers = KeyboardModifiers::R_ALT;
+
+/// Host keyboard layout used to turn text into key presses
+///
+/// The same key usage produces different characters depending on the layout
+/// configured on the host, so text must be mapped with the host's layout.
+pub trait KeyboardLayout {
+    fn name(&self) -> &'static str;
+
+    /// Maps a character to the modifiers and key usage typing it
+    ///
+    /// Returns `None` for characters without a key, including those which
+    /// would need a dead key sequence.
+    fn char_to_key(&self, c: char) -> Option<(KeyboardModifiers, u8)>;
+}
+
+/// Built-in layouts which can be selected at runtime
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LayoutId {
+    Us,
+    Uk,
+    De,
+    Fr,
+    Jis,
+}
+
+impl Default for LayoutId {
+    fn default() -> Self {
+        LayoutId::Us
+    }
+}
+
+impl LayoutId {
+    pub const ALL: [LayoutId; 5] = [
+        LayoutId::Us,
+        LayoutId::Uk,
+        LayoutId::De,
+        LayoutId::Fr,
+        LayoutId::Jis,
+    ];
+
+    /// Looks a layout up by its case-insensitive name
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|id| id.layout().name().eq_ignore_ascii_case(name))
+    }
+
+    pub fn layout(self) -> &'static dyn KeyboardLayout {
+        match self {
+            LayoutId::Us => &Us,
+            LayoutId::Uk => &Uk,
+            LayoutId::De => &De,
+            LayoutId::Fr => &Fr,
+            LayoutId::Jis => &Jis,
+        }
+    }
+}
+
+/// Maps the letters and digits shared by most Latin layouts
+fn common_char_to_key(c: char) -> Option<(KeyboardModifiers, u8)> {
+    let key = match c {
+        'a'..='z' => (NONE, 0x04 + (c as u8 - b'a')),
+        'A'..='Z' => (SHIFT, 0x04 + (c as u8 - b'A')),
+        '1'..='9' => (NONE, 0x1E + (c as u8 - b'1')),
+        '0' => (NONE, 0x27),
+        '\n' => (NONE, 0x28),
+        '\t' => (NONE, 0x2B),
+        ' ' => (NONE, 0x2C),
+        _ => return None,
+    };
+    Some(key)
+}
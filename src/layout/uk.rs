@@ -0,0 +1,54 @@
+use super::{common_char_to_key, KeyboardLayout, ALTGR, NONE, SHIFT};
+use crate::hid_report::KeyboardModifiers;
+
+/// UK English (ISO) layout
+#[derive(Clone, Copy, Debug)]
+pub struct Uk;
+
+impl KeyboardLayout for Uk {
+    fn name(&self) -> &'static str {
+        "uk"
+    }
+
+    fn char_to_key(&self, c: char) -> Option<(KeyboardModifiers, u8)> {
+        let key = match c {
+            '!' => (SHIFT, 0x1E),
+            '"' => (SHIFT, 0x1F),
+            '£' => (SHIFT, 0x20),
+            '$' => (SHIFT, 0x21),
+            '€' => (ALTGR, 0x21),
+            '%' => (SHIFT, 0x22),
+            '^' => (SHIFT, 0x23),
+            '&' => (SHIFT, 0x24),
+            '*' => (SHIFT, 0x25),
+            '(' => (SHIFT, 0x26),
+            ')' => (SHIFT, 0x27),
+            '-' => (NONE, 0x2D),
+            '_' => (SHIFT, 0x2D),
+            '=' => (NONE, 0x2E),
+            '+' => (SHIFT, 0x2E),
+            '[' => (NONE, 0x2F),
+            '{' => (SHIFT, 0x2F),
+            ']' => (NONE, 0x30),
+            '}' => (SHIFT, 0x30),
+            '#' => (NONE, 0x32),
+            '~' => (SHIFT, 0x32),
+            ';' => (NONE, 0x33),
+            ':' => (SHIFT, 0x33),
+            '\'' => (NONE, 0x34),
+            '@' => (SHIFT, 0x34),
+            '`' => (NONE, 0x35),
+            '¬' => (SHIFT, 0x35),
+            ',' => (NONE, 0x36),
+            '<' => (SHIFT, 0x36),
+            '.' => (NONE, 0x37),
+            '>' => (SHIFT, 0x37),
+            '/' => (NONE, 0x38),
+            '?' => (SHIFT, 0x38),
+            '\\' => (NONE, 0x64),
+            '|' => (SHIFT, 0x64),
+            _ => return common_char_to_key(c),
+        };
+        Some(key)
+    }
+}
@@ -1,20 +1,42 @@
-use core::{
-    cmp::min,
-    str::{self, Utf8Error},
-    usize,
-};
+use crate::command::MAX_REQUEST_LEN;
+use core::{cmp::min, str};
+
+/// Longest line kept, the longest request with its line ending
+pub const LINE_CAPACITY: usize = MAX_REQUEST_LEN + 1;
+
+/// A line which can't be taken as a command
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LineError<'a> {
+    /// Longer than `LINE_CAPACITY`, with the start of it. The rest is
+    /// dropped up to the next line ending.
+    TooLong(&'a str),
+    /// Not valid UTF-8, with the valid start of it
+    InvalidUtf8(&'a str),
+}
+
+impl<'a> LineError<'a> {
+    /// What could be read from the start of the line
+    pub fn start(&self) -> &'a str {
+        match self {
+            LineError::TooLong(start) | LineError::InvalidUtf8(start) => start,
+        }
+    }
+}
 
 #[derive(Debug)]
 pub struct LineBuffer {
-    buf: [u8; 64],
+    buf: [u8; LINE_CAPACITY],
     pos: usize,
+    /// Dropping the rest of a line which didn't fit
+    discarding: bool,
 }
 
 impl Default for LineBuffer {
     fn default() -> Self {
         Self {
-            buf: [0u8; 64],
+            buf: [0u8; LINE_CAPACITY],
             pos: 0,
+            discarding: false,
         }
     }
 }
@@ -29,37 +51,67 @@ impl LineBuffer {
         &self.buf
     }
 
-    pub fn feed(&mut self, data: &[u8]) -> Result<usize, Utf8Error> {
-        str::from_utf8(data)?;
+    /// Takes as much of `data` as fits, returning how many bytes were taken
+    ///
+    /// Lines have to be taken out with `get_line` before the rest of `data`
+    /// is fed.
+    pub fn feed(&mut self, data: &[u8]) -> usize {
+        let mut skipped = 0;
+        if self.discarding {
+            match data.iter().position(|&b| is_line_end(b)) {
+                Some(end) => {
+                    self.discarding = false;
+                    skipped = end + 1;
+                }
+                None => return data.len(),
+            }
+        }
+
+        let data = &data[skipped..];
         let copy_len = min(self.buf.len() - self.pos, data.len());
         self.buf[self.pos..(self.pos + copy_len)].copy_from_slice(&data[..copy_len]);
         self.pos += copy_len;
-        Ok(copy_len)
+        skipped + copy_len
     }
 
-    pub fn get_line<'a>(&mut self, s: &'a mut [u8]) -> Result<&'a str, ()> {
-        let mut copylen = if self.pos >= self.buf.len() {
-            Some(self.buf.len())
-        } else {
-            None
+    /// Takes the next line out of the buffer, with its line ending
+    ///
+    /// `s` must hold `LINE_CAPACITY` bytes. A line filling the whole buffer
+    /// without an ending is given as `LineError::TooLong`.
+    pub fn get_line<'a>(&mut self, s: &'a mut [u8]) -> Option<Result<&'a str, LineError<'a>>> {
+        let (copylen, too_long) = match self.buf[..self.pos].iter().position(|&b| is_line_end(b)) {
+            Some(end) => (end + 1, false),
+            None if self.pos >= self.buf.len() => (self.buf.len(), true),
+            None => return None,
         };
-        for (i, b) in (&self.buf[..self.pos]).iter().enumerate() {
-            if *b == b'\n' || *b == b'\r' {
-                copylen = Some(i + 1);
-                break;
-            }
-        }
 
-        if let Some(copylen) = copylen {
-            s[..copylen].copy_from_slice(&self.buf[..copylen]);
-            let linestr = str::from_utf8(&s[..copylen]).map_err(|_| ())?;
-            // Remove copied data
-            self.buf.copy_within(copylen.., 0);
-            self.pos -= copylen;
+        s[..copylen].copy_from_slice(&self.buf[..copylen]);
+        // Remove copied data, whether it is valid or not
+        self.buf.copy_within(copylen.., 0);
+        self.pos -= copylen;
 
-            Ok(linestr)
-        } else {
-            Err(())
+        let line = &s[..copylen];
+        let valid = match str::from_utf8(line) {
+            Ok(valid) => valid,
+            Err(e) => {
+                // Safety: `valid_up_to` ends the valid UTF-8 part
+                let start = unsafe { str::from_utf8_unchecked(&line[..e.valid_up_to()]) };
+                // A character cut off by a too long line is no encoding error
+                if !too_long || e.error_len().is_some() {
+                    self.discarding = too_long;
+                    return Some(Err(LineError::InvalidUtf8(start)));
+                }
+                start
+            }
+        };
+        if too_long {
+            self.discarding = true;
+            return Some(Err(LineError::TooLong(valid)));
         }
+        Some(Ok(valid))
     }
 }
+
+fn is_line_end(b: u8) -> bool {
+    b == b'\n' || b == b'\r'
+}
@@ -0,0 +1,279 @@
+//! Wireless link bookkeeping
+
+use crate::clock::Instant;
+use crate::serial::BlockingSerialWriter;
+use core::fmt::{self, Write};
+use core::sync::atomic::{AtomicU32, Ordering};
+
+/// Number of nRF24 RX pipes, each used by one transmitter
+pub const PIPE_COUNT: usize = 6;
+
+/// Length of the windows logged and shown by `stats`
+pub const WINDOW_MS: u32 = 10_000;
+/// Packets and losses needed by `LinkStats::loss_percent`
+const MIN_LOSS_SAMPLES: u32 = 20;
+
+/// Counters of the wireless link, shared with the `stats` command
+#[derive(Debug)]
+pub struct LinkStats {
+    /// Packets received, including duplicates
+    pub packets: AtomicU32,
+    /// Retransmitted frames which were dropped
+    pub duplicates: AtomicU32,
+    /// Frames missing from gaps in the sequence numbers
+    pub lost: AtomicU32,
+    /// Packets failing authentication or replayed
+    pub rejected: AtomicU32,
+    /// Rising edges of RPD without a packet arriving, see `LinkMonitor`
+    pub rpd_no_packet: AtomicU32,
+    /// Times the RX FIFO was found full, so later packets were dropped
+    pub overflows: AtomicU32,
+    /// Times RPD reported a carrier above -64dBm
+    pub carrier: AtomicU32,
+}
+
+pub static LINK_STATS: LinkStats = LinkStats::new();
+/// Counts of the last complete window of `WINDOW_MS`
+pub static LINK_WINDOW: LinkStats = LinkStats::new();
+
+/// The counters of `LinkStats` at one point in time
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+struct Counters {
+    packets: u32,
+    duplicates: u32,
+    lost: u32,
+    rejected: u32,
+    rpd_no_packet: u32,
+    overflows: u32,
+    carrier: u32,
+}
+
+impl Counters {
+    fn since(&self, base: &Counters) -> Counters {
+        Counters {
+            packets: self.packets.wrapping_sub(base.packets),
+            duplicates: self.duplicates.wrapping_sub(base.duplicates),
+            lost: self.lost.wrapping_sub(base.lost),
+            rejected: self.rejected.wrapping_sub(base.rejected),
+            rpd_no_packet: self.rpd_no_packet.wrapping_sub(base.rpd_no_packet),
+            overflows: self.overflows.wrapping_sub(base.overflows),
+            carrier: self.carrier.wrapping_sub(base.carrier),
+        }
+    }
+}
+
+impl fmt::Display for Counters {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "packets={} duplicates={} lost={} rejected={} rpd_no_packet={} overflows={} carrier={}",
+            self.packets,
+            self.duplicates,
+            self.lost,
+            self.rejected,
+            self.rpd_no_packet,
+            self.overflows,
+            self.carrier,
+        )
+    }
+}
+
+impl LinkStats {
+    const fn new() -> Self {
+        Self {
+            packets: AtomicU32::new(0),
+            duplicates: AtomicU32::new(0),
+            lost: AtomicU32::new(0),
+            rejected: AtomicU32::new(0),
+            rpd_no_packet: AtomicU32::new(0),
+            overflows: AtomicU32::new(0),
+            carrier: AtomicU32::new(0),
+        }
+    }
+
+    pub fn add(counter: &AtomicU32, n: u32) {
+        counter.fetch_add(n, Ordering::Relaxed);
+    }
+
+    fn counters(&self) -> Counters {
+        Counters {
+            packets: self.packets.load(Ordering::Relaxed),
+            duplicates: self.duplicates.load(Ordering::Relaxed),
+            lost: self.lost.load(Ordering::Relaxed),
+            rejected: self.rejected.load(Ordering::Relaxed),
+            rpd_no_packet: self.rpd_no_packet.load(Ordering::Relaxed),
+            overflows: self.overflows.load(Ordering::Relaxed),
+            carrier: self.carrier.load(Ordering::Relaxed),
+        }
+    }
+
+    fn store(&self, counters: &Counters) {
+        self.packets.store(counters.packets, Ordering::Relaxed);
+        self.duplicates
+            .store(counters.duplicates, Ordering::Relaxed);
+        self.lost.store(counters.lost, Ordering::Relaxed);
+        self.rejected.store(counters.rejected, Ordering::Relaxed);
+        self.rpd_no_packet
+            .store(counters.rpd_no_packet, Ordering::Relaxed);
+        self.overflows.store(counters.overflows, Ordering::Relaxed);
+        self.carrier.store(counters.carrier, Ordering::Relaxed);
+    }
+
+    /// Share of frames lost, once there were enough of them to tell
+    ///
+    /// `rpd_no_packet` is left out, it counts foreign traffic as well.
+    pub fn loss_percent(&self) -> Option<u32> {
+        let counters = self.counters();
+        let lost = counters.lost;
+        let total = counters.packets + lost;
+        if total < MIN_LOSS_SAMPLES {
+            return None;
+        }
+        Some(lost * 100 / total)
+    }
+
+    /// Prints the counters since startup and of the last window over serial
+    pub fn print(&self) {
+        let mut writer = BlockingSerialWriter;
+        write!(writer, "STATS {}\r\n", self.counters()).ok();
+        write!(
+            writer,
+            "STATS window={}s {}\r\n",
+            WINDOW_MS / 1000,
+            LINK_WINDOW.counters(),
+        )
+        .ok();
+        writer
+            .write_str("STATS the nRF24 drops packets failing CRC without counting them\r\n")
+            .ok();
+    }
+}
+
+/// Turns samples of the nRF24 status registers into `LINK_STATS`
+///
+/// The nRF24 drops packets failing CRC without telling. A carrier which comes
+/// without a packet is counted in `rpd_no_packet`, which holds such packets
+/// as well as foreign traffic on the channel.
+#[derive(Debug)]
+pub struct LinkMonitor {
+    /// RPD of the last sample
+    carrier: bool,
+    /// RX_FULL of the last sample
+    rx_full: bool,
+    /// Packets handled since the last sample
+    packets: u32,
+    window_start: Instant,
+    /// `LINK_STATS` at the start of the window
+    window_base: Counters,
+}
+
+impl LinkMonitor {
+    pub fn new() -> Self {
+        Self {
+            carrier: false,
+            rx_full: false,
+            packets: 0,
+            window_start: Instant::now(),
+            window_base: Counters::default(),
+        }
+    }
+
+    pub fn received(&mut self, packets: u32) {
+        self.packets += packets;
+    }
+
+    /// Takes RPD and RX_FULL, counting them against the packets handled
+    /// since the last sample
+    pub fn sample(&mut self, carrier: bool, rx_full: bool) {
+        if carrier && !self.carrier {
+            LinkStats::add(&LINK_STATS.carrier, 1);
+            if self.packets == 0 {
+                LinkStats::add(&LINK_STATS.rpd_no_packet, 1);
+            }
+        }
+        if rx_full && !self.rx_full {
+            warn!("RX FIFO overflow");
+            LinkStats::add(&LINK_STATS.overflows, 1);
+        }
+        self.carrier = carrier;
+        self.rx_full = rx_full;
+        self.packets = 0;
+    }
+
+    /// Closes the window once `WINDOW_MS` passed, logging its counts.
+    /// Returns whether it did.
+    pub fn poll_window(&mut self) -> bool {
+        if self.window_start.elapsed_ms() < WINDOW_MS {
+            return false;
+        }
+        self.window_start = self.window_start.add_ms(WINDOW_MS);
+
+        let counters = LINK_STATS.counters();
+        let window = counters.since(&self.window_base);
+        self.window_base = counters;
+        LINK_WINDOW.store(&window);
+        info!("Link {}s: {}", WINDOW_MS / 1000, window);
+        true
+    }
+}
+
+/// Last frame sequence number seen from each pipe
+///
+/// A transmitter which misses the ACK of a frame sends it again, so a frame
+/// with the same sequence number as the previous one is a duplicate. A
+/// transmitter which has just started marks its frames with
+/// `packet::RESTART_FLAG`, so the gap to the sequence numbers before isn't
+/// counted as lost.
+#[derive(Debug)]
+pub struct SeqTracker {
+    last: [Option<u8>; PIPE_COUNT],
+}
+
+impl SeqTracker {
+    pub const fn new() -> Self {
+        Self {
+            last: [None; PIPE_COUNT],
+        }
+    }
+
+    /// Forgets the last sequence number of a pipe, e.g. for a new transmitter
+    pub fn reset(&mut self, pipe: u8) {
+        if let Some(last) = self.last.get_mut(usize::from(pipe)) {
+            *last = None;
+        }
+    }
+
+    /// Sequence number of the last frame accepted from a pipe
+    pub fn last(&self, pipe: u8) -> Option<u8> {
+        self.last.get(usize::from(pipe)).copied().flatten()
+    }
+
+    /// Records the sequence number of a frame, returning whether it is new
+    pub fn accept(&mut self, pipe: u8, seq: u8, restart: bool) -> bool {
+        let last = match self.last.get_mut(usize::from(pipe)) {
+            Some(last) => last,
+            None => return true,
+        };
+
+        if let Some(prev) = *last {
+            match seq.wrapping_sub(prev) {
+                0 => {
+                    debug!("Duplicate frame #{} on pipe {}", seq, pipe);
+                    LinkStats::add(&LINK_STATS.duplicates, 1);
+                    return false;
+                }
+                _ if restart => info!("Frame sequence restarted on pipe {}", pipe),
+                1 => (),
+                diff if diff < 0x80 => {
+                    debug!("{} frames lost on pipe {}", diff - 1, pipe);
+                    LinkStats::add(&LINK_STATS.lost, u32::from(diff - 1));
+                }
+                // Far behind the last one, so the transmitter has restarted
+                // without saying
+                _ => info!("Frame sequence restarted on pipe {}", pipe),
+            }
+        }
+        *last = Some(seq);
+        true
+    }
+}
@@ -4,29 +4,39 @@
 #[macro_use]
 extern crate bitflags;
 #[macro_use]
-extern crate cortex_m_rt;
-#[macro_use]
 extern crate log;
 
-use core::cell::RefCell;
-
-use app::App;
-use command::Commands;
-use cortex_m::interrupt::{free, Mutex};
-use cortex_m::peripheral::NVIC;
-use embedded_nrf24l01::{setup::*, Configuration, CrcMode, DataRate, NRF24L01};
-use hid_report::{CursorReport, KeyboardReport, MouseReport};
-use line_buffer::LineBuffer;
-use nrf24_mode::{NRF24Device, NRF24Mode};
+use ack::{AckPayloads, Status, StatusFlags};
+use app::{App, ExecError, Shared, Source};
+use clock::{Instant, SYSCLK_HZ};
+use command::{Commands, ParseError, Request};
+use config::Config;
+use cortex_m_rt::exception;
+use embedded_nrf24l01::{setup::*, Configuration, NRF24L01};
+use hid_report::{KeyboardLeds, KeyboardReport, CONTROL_DESC, POINTER_DESC};
+use hopping::Hopper;
+use keyboard_leds::SetReport;
+use line_buffer::{LineBuffer, LineError, LINE_CAPACITY};
+use link::{LinkMonitor, LinkStats, SeqTracker, LINK_STATS, LINK_WINDOW, PIPE_COUNT};
+use nrf24_mode::{reg, NRF24Device, NRF24Mode};
+use packet::Packet;
+use pairing::{Pairing, Progress};
 use panic_semihosting as _;
+use queue::Queue;
+use rtic::cyccnt::U32Ext;
+use rtic::Mutex;
+use rx::RX_QUEUE;
+use secure::{AuthError, SecureLink, MAX_PACKET_LEN};
+use serial::SerialTx;
 use stm32l4xx_hal::{
-    interrupt,
+    gpio::*,
     otg_fs::{UsbBus, USB},
     prelude::*,
     rcc::{PllConfig, PllDivider, PllSource},
     spi::Spi,
-    stm32::{Interrupt, Peripherals},
+    stm32::Interrupt,
 };
+use usb::{HidClasses, UsbBusType};
 use usb_device::{class_prelude::UsbBusAllocator, prelude::*};
 use usbd_hid::descriptor::generator_prelude::*;
 use usbd_hid::hid_class::HIDClass;
@@ -34,30 +44,37 @@ use usbd_serial::SerialPort;
 
 use usb_logger::UsbLogger;
 
-const NRF24_ADDRESS: &[u8; 5] = b"\x2f\xa6\x37\x89\x73";
-const NRF24_CHANNEL: u8 = 82;
-
-static mut EP_MEMORY: [u32; 320] = [0; 320];
+static SERIAL_QUEUE: Queue<Request, 32> = Queue::new();
 
-type MutexCell<T> = Mutex<RefCell<Option<T>>>;
-type UsbType = UsbBus<USB>;
-
-static mut USB_BUS: Option<UsbBusAllocator<UsbType>> = None;
-static USB_DEV: MutexCell<UsbDevice<UsbType>> = Mutex::new(RefCell::new(None));
-static USB_HID_CURSOR: MutexCell<HIDClass<UsbType>> = Mutex::new(RefCell::new(None));
-static USB_HID_MOUSE: MutexCell<HIDClass<UsbType>> = Mutex::new(RefCell::new(None));
-static USB_HID_KBD: MutexCell<HIDClass<UsbType>> = Mutex::new(RefCell::new(None));
-static USB_SER: MutexCell<SerialPort<UsbType>> = Mutex::new(RefCell::new(None));
-static NRF24: MutexCell<NRF24Mode<NRF24Device>> = Mutex::new(RefCell::new(None));
-static SERIAL_BUF: MutexCell<LineBuffer> = Mutex::new(RefCell::new(None));
-static SERIAL_CMD: MutexCell<Commands> = Mutex::new(RefCell::new(None));
+/// Period of the `tick` task
+const TICK_CYCLES: u32 = SYSCLK_HZ / 1000;
 
+mod ack;
 mod app;
+mod clock;
 mod command;
+mod config;
+mod crc;
+mod flash;
 mod hid_report;
+mod hopping;
+mod keyboard_leds;
+mod layout;
 mod line_buffer;
+mod link;
 mod nrf24_mode;
+mod packet;
+mod pairing;
+mod queue;
+mod radio;
+mod rx;
+mod secure;
+mod serial;
+mod slide_timer;
+mod timer_wheel;
+mod usb;
 mod usb_logger;
+mod usb_power;
 
 static USB_LOGGER: UsbLogger = UsbLogger;
 
@@ -99,250 +116,665 @@ fn enable_pllq_48mhz() {
     rcc.ccipr.modify(|_, w| unsafe { w.clk48sel().bits(0x02) });
 }
 
-#[entry]
-fn main() -> ! {
-    let dp = Peripherals::take().unwrap();
-
-    let mut flash = dp.FLASH.constrain();
-    let mut rcc = dp.RCC.constrain();
-    let mut pwr = dp.PWR.constrain(&mut rcc.apb1r1);
-
-    // Set HCLK to 48MHz
-    let clocks = rcc
-        .cfgr
-        .pll_source(PllSource::HSI16)
-        .sysclk_with_pll(48.mhz(), PllConfig::new(2, 12, PllDivider::Div2))
-        .hclk(48.mhz())
-        .pclk1(24.mhz())
-        .pclk2(24.mhz())
-        .freeze(&mut flash.acr, &mut pwr);
-    // Output 48MHz to USB clock source
-    enable_pllq_48mhz();
-
-    enable_crs();
-
-    // disable Vddusb power isolation
-    enable_usb_pwr();
-
-    // Setup basic GPIO
-    let mut gpioa = dp.GPIOA.split(&mut rcc.ahb2);
-    let mut gpiob = dp.GPIOB.split(&mut rcc.ahb2);
-    let mut gpioc = dp.GPIOC.split(&mut rcc.ahb2);
-    let mut led = gpioa
-        .pa5
-        .into_push_pull_output(&mut gpioa.moder, &mut gpioa.otyper);
-    let usr_btn = gpioc
-        .pc13
-        .into_pull_up_input(&mut gpioc.moder, &mut gpioc.pupdr);
-
-    // Setup USB
-    let usb = USB {
-        usb_global: dp.OTG_FS_GLOBAL,
-        usb_device: dp.OTG_FS_DEVICE,
-        usb_pwrclk: dp.OTG_FS_PWRCLK,
-        pin_dm: gpioa
-            .pa11
-            .into_push_pull_output(&mut gpioa.moder, &mut gpioa.otyper)
-            .into_af10(&mut gpioa.moder, &mut gpioa.afrh),
-        pin_dp: gpioa
-            .pa12
-            .into_push_pull_output(&mut gpioa.moder, &mut gpioa.otyper)
-            .into_af10(&mut gpioa.moder, &mut gpioa.afrh),
-        hclk: clocks.hclk(),
-    };
-    let usb_bus = UsbBus::new(usb, unsafe { &mut EP_MEMORY });
-    free(|_| unsafe {
-        // Safety: Interrupt-free section
-        USB_BUS = Some(usb_bus);
-    });
-
-    free(|cs| {
-        // Safety: Interrupt-free section
-        USB_SER
-            .borrow(cs)
-            .replace(Some(SerialPort::new(unsafe { USB_BUS.as_ref().unwrap() })));
-        USB_HID_CURSOR.borrow(cs).replace(Some(HIDClass::new(
-            unsafe { USB_BUS.as_ref().unwrap() },
-            CursorReport::desc(),
-            100,
-        )));
-        USB_HID_MOUSE.borrow(cs).replace(Some(HIDClass::new(
-            unsafe { USB_BUS.as_ref().unwrap() },
-            MouseReport::desc(),
-            100,
-        )));
-        USB_HID_KBD.borrow(cs).replace(Some(HIDClass::new(
-            unsafe { USB_BUS.as_ref().unwrap() },
-            KeyboardReport::desc(),
-            100,
-        )));
-        USB_DEV.borrow(cs).replace(Some(
-            UsbDeviceBuilder::new(
-                unsafe { USB_BUS.as_ref().unwrap() },
-                UsbVidPid(0x16c0, 0x0487),
-            )
+#[rtic::app(device = stm32l4xx_hal::stm32, peripherals = true, monotonic = rtic::cyccnt::CYCCNT)]
+const APP: () = {
+    struct Resources {
+        usb_dev: UsbDevice<'static, UsbBusType>,
+        usb_ser: SerialPort<'static, UsbBusType>,
+        hid: HidClasses,
+        serial_buf: LineBuffer,
+        serial_tx: SerialTx,
+        radio: NRF24Mode<NRF24Device>,
+        config: Config,
+        app: App,
+        /// Sequence id of the serial command whose job is running
+        pending_seq: Option<u16>,
+        pairing: Option<Pairing>,
+        seq_tracker: SeqTracker,
+        secure_link: SecureLink,
+        ack_payloads: AckPayloads,
+        link_monitor: LinkMonitor,
+        hopper: Hopper,
+        led: PA5<Output<PushPull>>,
+        usr_btn: PC13<Input<PullUp>>,
+        /// Num, Caps and Scroll Lock LEDs
+        lock_leds: (
+            PC0<Output<PushPull>>,
+            PC1<Output<PushPull>>,
+            PC2<Output<PushPull>>,
+        ),
+    }
+
+    #[init(schedule = [tick])]
+    fn init(cx: init::Context) -> init::LateResources {
+        static mut EP_MEMORY: [u32; 320] = [0; 320];
+        static mut USB_BUS: Option<UsbBusAllocator<UsbBusType>> = None;
+
+        let dp = cx.device;
+        let mut cp = cx.core;
+
+        let mut flash = dp.FLASH.constrain();
+        let mut rcc = dp.RCC.constrain();
+        let mut pwr = dp.PWR.constrain(&mut rcc.apb1r1);
+
+        // Set HCLK to 48MHz
+        let clocks = rcc
+            .cfgr
+            .pll_source(PllSource::HSI16)
+            .sysclk_with_pll(48.mhz(), PllConfig::new(2, 12, PllDivider::Div2))
+            .hclk(48.mhz())
+            .pclk1(24.mhz())
+            .pclk2(24.mhz())
+            .freeze(&mut flash.acr, &mut pwr);
+        // Output 48MHz to USB clock source
+        enable_pllq_48mhz();
+
+        // Start the cycle counter used by the RTIC scheduler
+        cp.DCB.enable_trace();
+        cp.DWT.enable_cycle_counter();
+        clock::start();
+
+        enable_crs();
+
+        // disable Vddusb power isolation
+        enable_usb_pwr();
+
+        // Setup basic GPIO
+        let mut gpioa = dp.GPIOA.split(&mut rcc.ahb2);
+        let mut gpiob = dp.GPIOB.split(&mut rcc.ahb2);
+        let mut gpioc = dp.GPIOC.split(&mut rcc.ahb2);
+        let led = gpioa
+            .pa5
+            .into_push_pull_output(&mut gpioa.moder, &mut gpioa.otyper);
+        let usr_btn = gpioc
+            .pc13
+            .into_pull_up_input(&mut gpioc.moder, &mut gpioc.pupdr);
+        let lock_leds = (
+            gpioc
+                .pc0
+                .into_push_pull_output(&mut gpioc.moder, &mut gpioc.otyper),
+            gpioc
+                .pc1
+                .into_push_pull_output(&mut gpioc.moder, &mut gpioc.otyper),
+            gpioc
+                .pc2
+                .into_push_pull_output(&mut gpioc.moder, &mut gpioc.otyper),
+        );
+
+        // Setup USB
+        let usb = USB {
+            usb_global: dp.OTG_FS_GLOBAL,
+            usb_device: dp.OTG_FS_DEVICE,
+            usb_pwrclk: dp.OTG_FS_PWRCLK,
+            pin_dm: gpioa
+                .pa11
+                .into_push_pull_output(&mut gpioa.moder, &mut gpioa.otyper)
+                .into_af10(&mut gpioa.moder, &mut gpioa.afrh),
+            pin_dp: gpioa
+                .pa12
+                .into_push_pull_output(&mut gpioa.moder, &mut gpioa.otyper)
+                .into_af10(&mut gpioa.moder, &mut gpioa.afrh),
+            hclk: clocks.hclk(),
+        };
+        *USB_BUS = Some(UsbBus::new(usb, EP_MEMORY));
+        let usb_bus = USB_BUS.as_ref().unwrap();
+
+        let usb_ser = SerialPort::new(usb_bus);
+        let hid = HidClasses {
+            pointer: HIDClass::new(usb_bus, POINTER_DESC, 100),
+            kbd: HIDClass::new(usb_bus, KeyboardReport::desc(), 100),
+            ctrl: HIDClass::new(usb_bus, CONTROL_DESC, 100),
+        };
+        let usb_dev = UsbDeviceBuilder::new(usb_bus, UsbVidPid(0x16c0, 0x0487))
             .manufacturer("Leo")
             .product("Smart presenter")
             .serial_number("TEST0000")
-            .build(),
-        ));
-
-        SERIAL_BUF.borrow(cs).replace(Some(LineBuffer::new()));
-    });
-
-    USB_LOGGER.init();
-    info!("USB initialized");
-
-    // Setup NRF24L01
-    let spi_sck = gpiob
-        .pb3
-        .into_floating_input(&mut gpiob.moder, &mut gpiob.pupdr)
-        .into_af5(&mut gpiob.moder, &mut gpiob.afrl);
-    let spi_miso = gpiob
-        .pb4
-        .into_floating_input(&mut gpiob.moder, &mut gpiob.pupdr)
-        .into_af5(&mut gpiob.moder, &mut gpiob.afrl);
-    let spi_mosi = gpiob
-        .pb5
-        .into_floating_input(&mut gpiob.moder, &mut gpiob.pupdr)
-        .into_af5(&mut gpiob.moder, &mut gpiob.afrl);
-    let spi = Spi::spi1(
-        dp.SPI1,
-        (spi_sck, spi_miso, spi_mosi),
-        spi_mode(),
-        clock_mhz(),
-        clocks,
-        &mut rcc.apb2,
-    );
-    let nrf24_ce = gpiob
-        .pb9
-        .into_push_pull_output(&mut gpiob.moder, &mut gpiob.otyper);
-    let nrf24_csn = gpiob
-        .pb8
-        .into_push_pull_output(&mut gpiob.moder, &mut gpiob.otyper);
-    let mut nrf24l01 =
-        NRF24L01::new(nrf24_ce, nrf24_csn, spi).expect("Failed to initialize NRF24L01");
-
-    nrf24l01
-        .set_rf(&DataRate::R2Mbps, 3)
-        .expect("Failed to set RF power");
-    nrf24l01
-        .set_frequency(NRF24_CHANNEL)
-        .expect("Failed to set channel");
-    nrf24l01
-        .set_crc(CrcMode::OneByte)
-        .expect("Failed to set CRC setting");
-    nrf24l01
-        .set_auto_ack(&[true, true, true, true, true, true])
-        .expect("Failed to enable auto ACK");
-    nrf24l01
-        .set_rx_addr(0, NRF24_ADDRESS)
-        .expect("Failed to set Rx 0 address");
-    nrf24l01
-        .set_pipes_rx_lengths(&[None; 6])
-        .expect("Failed to set payload length");
-    nrf24l01
-        .set_pipes_rx_enable(&[true, false, false, false, false, false])
-        .expect("Failed to enable Rxs");
-    nrf24l01
-        .set_interrupt_mask(false, true, true)
-        .expect("Failed to set interrupt mask");
-
-    free(|cs| {
-        NRF24
-            .borrow(cs)
-            .replace(Some(NRF24Mode::Rx(nrf24l01.rx().unwrap())));
-    });
-
-    info!("NRF24L01 initialized");
-
-    unsafe {
-        NVIC::unmask(Interrupt::OTG_FS);
-        NVIC::unmask(Interrupt::EXTI9_5);
+            .supports_remote_wakeup(true)
+            .build();
+
+        USB_LOGGER.init();
+        info!("USB initialized");
+
+        let config = Config::load();
+        log::set_max_level(config.log_level);
+
+        // Setup NRF24L01
+        let spi_sck = gpiob
+            .pb3
+            .into_floating_input(&mut gpiob.moder, &mut gpiob.pupdr)
+            .into_af5(&mut gpiob.moder, &mut gpiob.afrl);
+        let spi_miso = gpiob
+            .pb4
+            .into_floating_input(&mut gpiob.moder, &mut gpiob.pupdr)
+            .into_af5(&mut gpiob.moder, &mut gpiob.afrl);
+        let spi_mosi = gpiob
+            .pb5
+            .into_floating_input(&mut gpiob.moder, &mut gpiob.pupdr)
+            .into_af5(&mut gpiob.moder, &mut gpiob.afrl);
+        let spi = Spi::spi1(
+            dp.SPI1,
+            (spi_sck, spi_miso, spi_mosi),
+            spi_mode(),
+            clock_mhz(),
+            clocks,
+            &mut rcc.apb2,
+        );
+        let nrf24_ce = gpiob
+            .pb9
+            .into_push_pull_output(&mut gpiob.moder, &mut gpiob.otyper);
+        let nrf24_csn = gpiob
+            .pb8
+            .into_push_pull_output(&mut gpiob.moder, &mut gpiob.otyper);
+        let _nrf24_irq = gpiob
+            .pb7
+            .into_floating_input(&mut gpiob.moder, &mut gpiob.pupdr);
+        let mut nrf24l01 =
+            NRF24L01::new(nrf24_ce, nrf24_csn, spi).expect("Failed to initialize NRF24L01");
+
+        radio::write_settings(&mut nrf24l01, &config).expect("Failed to set radio settings");
+        nrf24l01
+            .set_auto_ack(&[true, true, true, true, true, true])
+            .expect("Failed to enable auto ACK");
+        nrf24l01
+            .set_pipes_rx_lengths(&[None; 6])
+            .expect("Failed to set payload length");
+        nrf24l01
+            .set_interrupt_mask(false, true, true)
+            .expect("Failed to set interrupt mask");
+
+        let mut radio = NRF24Mode::Rx(nrf24l01.rx().unwrap());
+        AckPayloads::enable(&mut radio);
+        rx::listen();
+        // Packets may have come before EXTI was set up
+        rtic::pend(Interrupt::EXTI9_5);
+
+        info!("NRF24L01 initialized");
+
+        let mut secure_link = SecureLink::new();
+        secure_link.set_keys(&config.paired.key);
+        secure_link.set_floors(&config.counter_floor);
+
+        cx.schedule.tick(cx.start + TICK_CYCLES.cycles()).unwrap();
+
+        init::LateResources {
+            usb_dev,
+            usb_ser,
+            hid,
+            serial_buf: LineBuffer::new(),
+            serial_tx: SerialTx::new(),
+            radio,
+            app: App::new(&config),
+            hopper: Hopper::new(&config),
+            config,
+            pending_seq: None,
+            pairing: None,
+            seq_tracker: SeqTracker::new(),
+            secure_link,
+            ack_payloads: AckPayloads::new(),
+            link_monitor: LinkMonitor::new(),
+            led,
+            usr_btn,
+            lock_leds,
+        }
     }
 
-    let mut btn_state = false;
+    /// Polls the USB classes, sends out queued serial output and queues the
+    /// serial commands received
+    #[task(
+        binds = OTG_FS,
+        priority = 3,
+        resources = [usb_dev, usb_ser, hid, serial_buf, serial_tx],
+        spawn = [dispatch]
+    )]
+    fn usb_poll(cx: usb_poll::Context) {
+        let usb_poll::Resources {
+            usb_dev,
+            usb_ser,
+            hid,
+            serial_buf,
+            serial_tx,
+        } = cx.resources;
+
+        let mut buf = [0u8; 64];
+        let mut len = 0;
+        if usb_dev.poll(&mut [
+            &mut SetReport,
+            usb_ser,
+            &mut hid.pointer,
+            &mut hid.kbd,
+            &mut hid.ctrl,
+        ]) {
+            len = usb_ser.read(&mut buf).unwrap_or(0);
+        }
+        usb_power::update(usb_dev.state(), usb_dev.remote_wakeup_enabled());
+        let mut leds = [0u8; 1];
+        if let Ok(1) = hid.kbd.pull_raw_output(&mut leds) {
+            keyboard_leds::store(leds[0]);
+        }
+        serial_tx.drain(usb_ser);
+
+        // Lines are taken out as they come, so a long one doesn't push the
+        // next ones out of the buffer
+        let mut data = &buf[..len];
+        let mut line = [0u8; LINE_CAPACITY];
+        loop {
+            let fed = serial_buf.feed(data);
+            data = &data[fed..];
+            while let Some(cmdline) = serial_buf.get_line(&mut line) {
+                if queue_line(cmdline) {
+                    cx.spawn.dispatch().ok();
+                }
+            }
+            if data.is_empty() {
+                break;
+            }
+        }
+    }
 
-    let mut app = App::new();
+    /// Moves received packets from the nRF24 to `RX_QUEUE`
+    #[task(binds = EXTI9_5, priority = 2, resources = [radio], spawn = [dispatch])]
+    fn radio_rx(cx: radio_rx::Context) {
+        rx::clear_pending();
+        rx::drain(cx.resources.radio);
+        if !RX_QUEUE.is_empty() {
+            cx.spawn.dispatch().ok();
+        }
+    }
 
-    let mut led_cnt: u32 = 0;
-    loop {
-        if led_cnt % 1000 == 0 {
-            let led_state = led_cnt / 1000;
-            if led_state % 2 == 0 {
-                led.set_high().ok();
+    /// Runs queued serial commands and wireless packets
+    #[task(
+        priority = 1,
+        resources = [
+            app,
+            hid,
+            radio,
+            config,
+            pending_seq,
+            seq_tracker,
+            secure_link,
+            ack_payloads,
+            link_monitor,
+            hopper,
+        ]
+    )]
+    fn dispatch(cx: dispatch::Context) {
+        let dispatch::Resources {
+            app,
+            hid,
+            radio,
+            config,
+            pending_seq,
+            seq_tracker,
+            secure_link,
+            ack_payloads,
+            link_monitor,
+            hopper,
+        } = cx.resources;
+        let mut res = Shared {
+            hid,
+            radio,
+            config,
+            secure_link,
+            seq_tracker,
+        };
+
+        // Serial commands run one after another, so wait for a running one
+        while !app.is_busy() {
+            if let Some(req) = SERIAL_QUEUE.dequeue() {
+                let result = app.process_cmd(&mut res, Source::Serial, req.cmd);
+                if app.is_busy() {
+                    *pending_seq = req.seq;
+                } else {
+                    reply(req.seq, result);
+                }
             } else {
-                led.set_low().ok();
+                break;
             }
         }
-        if usr_btn.is_low().unwrap() {
-            btn_state = true;
-        } else if btn_state && usr_btn.is_high().unwrap() {
-            btn_state = false;
+        let dropped = SERIAL_QUEUE.take_dropped();
+        if dropped > 0 {
+            warn!("{} serial commands dropped", dropped);
         }
 
-        free(|cs| {
-            let mut serial_cmd_ref = SERIAL_CMD.borrow(cs).borrow_mut();
-            if let Some(serial_cmd) = *serial_cmd_ref {
-                app.process_cmd(serial_cmd);
+        let mut received = [false; PIPE_COUNT];
+        let mut packets = 0;
+        while let Some(packet) = RX_QUEUE.dequeue() {
+            packets += 1;
+            // Forged packets take the ACK payload too, but only authentic
+            // ones keep the pipe active for hopping and the refill
+            ack_payloads.sent(packet.pipe);
+            if process_packet(app, &mut res, hopper, packet.pipe, packet.as_ref()) {
+                received[usize::from(packet.pipe)] = true;
+            }
+        }
+        LinkStats::add(&LINK_STATS.overflows, RX_QUEUE.take_dropped());
+        if packets == 0 {
+            return;
+        }
+        link_monitor.received(packets);
+        hopper.received(&received);
+
+        let status = host_status(app, hopper.announced());
+        let seq_tracker = &*res.seq_tracker;
+        let secure_link = &*res.secure_link;
+        res.radio.lock(|nrf24l01| {
+            for pipe in (0..PIPE_COUNT as u8).filter(|&pipe| received[usize::from(pipe)]) {
+                ack_payloads.refill(
+                    nrf24l01,
+                    pipe,
+                    &status,
+                    seq_tracker.last(pipe),
+                    secure_link.next_counter(pipe),
+                );
             }
-            *serial_cmd_ref = None;
         });
+    }
 
-        free(|cs| {
-            let mut nrf24l01_ref = NRF24.borrow(cs).borrow_mut();
-            let nrf24l01 = nrf24l01_ref.as_mut().unwrap();
+    /// Runs everything which goes by time, every millisecond
+    #[task(
+        priority = 1,
+        schedule = [tick],
+        spawn = [dispatch],
+        resources = [
+            app,
+            hid,
+            radio,
+            config,
+            pending_seq,
+            pairing,
+            seq_tracker,
+            secure_link,
+            ack_payloads,
+            link_monitor,
+            hopper,
+            led,
+            usr_btn,
+            lock_leds,
+        ]
+    )]
+    fn tick(cx: tick::Context) {
+        static mut BTN_PRESSED_AT: Option<Instant> = None;
+        static mut BTN_HANDLED: bool = false;
+
+        let tick::Resources {
+            app,
+            mut hid,
+            mut radio,
+            config,
+            pending_seq,
+            pairing,
+            seq_tracker,
+            secure_link,
+            ack_payloads,
+            link_monitor,
+            hopper,
+            led,
+            usr_btn,
+            lock_leds,
+        } = cx.resources;
+
+        if let Some(pairing) = pairing.as_ref() {
+            if pairing.led_on() {
+                led.set_high().ok();
+            } else {
+                led.set_low().ok();
+            }
+        } else if (clock::millis() / 1000) % 2 == 0 {
+            led.set_high().ok();
+        } else {
+            led.set_low().ok();
+        }
+        // Reflect the lock state set by the host
+        let keyboard_leds = app.keyboard_leds();
+        if keyboard_leds.contains(KeyboardLeds::NUM_LOCK) {
+            lock_leds.0.set_high().ok();
+        } else {
+            lock_leds.0.set_low().ok();
+        }
+        if keyboard_leds.contains(KeyboardLeds::CAPS_LOCK) {
+            lock_leds.1.set_high().ok();
+        } else {
+            lock_leds.1.set_low().ok();
+        }
+        if keyboard_leds.contains(KeyboardLeds::SCROLL_LOCK) {
+            lock_leds.2.set_high().ok();
+        } else {
+            lock_leds.2.set_low().ok();
+        }
 
-            nrf24l01.configuration_mut().clear_interrupts().ok();
+        let mut start_pairing = false;
+        if usr_btn.is_low().unwrap() {
+            let pressed_at = *BTN_PRESSED_AT.get_or_insert_with(Instant::now);
+            if !*BTN_HANDLED && pressed_at.elapsed_ms() >= pairing::HOLD_MS {
+                *BTN_HANDLED = true;
+                start_pairing = pairing.is_none();
+            }
+        } else {
+            *BTN_PRESSED_AT = None;
+            *BTN_HANDLED = false;
+        }
 
-            let nrf24l01_rx = nrf24l01.to_rx();
-            while nrf24l01_rx.can_read().unwrap().is_some() {
-                let packet = nrf24l01_rx.read().unwrap();
+        usb_power::poll();
+        if let Some(result) = app.poll(&mut hid) {
+            reply(pending_seq.take(), result);
+        }
+        // Serial commands waiting for the job which just finished
+        if !app.is_busy() && !SERIAL_QUEUE.is_empty() {
+            cx.spawn.dispatch().ok();
+        }
 
-                if let Ok(s) = core::str::from_utf8(packet.as_ref()) {
-                    debug!("Wireless command: {:?}", s);
-                    if let Ok(cmd) = s.trim_end().parse::<Commands>() {
-                        debug!("Parsed command: {:?}", cmd);
-                        app.process_cmd(cmd);
-                    };
+        let paired = radio.lock(|nrf24l01| {
+            if start_pairing {
+                // Pairing happens on the home channel
+                hopper.go_home(nrf24l01, config);
+                rx::pause(true);
+                *pairing = Some(Pairing::start(nrf24l01));
+                // Pairing flushed the status payloads
+                ack_payloads.clear();
+            }
+            if let Some(pairing_mode) = pairing.as_mut() {
+                match pairing_mode.poll(nrf24l01) {
+                    Progress::Pending => (),
+                    Progress::Paired(device) => {
+                        pairing::listen_on(nrf24l01, &device.address);
+                        // The keys of the other pipes derive from it
+                        secure_link.set_keys(&device.key);
+                        for pipe in 0..PIPE_COUNT as u8 {
+                            seq_tracker.reset(pipe);
+                        }
+                        return Some(device);
+                    }
+                    Progress::TimedOut => pairing::listen_on(nrf24l01, &config.paired.address),
+                    Progress::Finished => {
+                        *pairing = None;
+                        ack_payloads.clear();
+                        rx::pause(false);
+                        rtic::pend(Interrupt::EXTI9_5);
+                    }
                 }
+                return None;
+            }
+
+            hopper.poll(nrf24l01, config);
+            if hopper.is_scanning() {
+                return None;
+            }
+
+            let carrier = nrf24l01.read_register(reg::RPD) & 0x01 != 0;
+            link_monitor.sample(carrier, rx::take_rx_full());
+            if link_monitor.poll_window() {
+                hopper.window_closed(config, &LINK_WINDOW);
             }
+            None
         });
+        // Flash is written outside the lock, which would hold off the radio
+        // for the whole erase
+        if let Some(device) = paired {
+            config.paired = device;
+            config.counter_floor = secure_link.floors();
+            if let Err(e) = config.save() {
+                error!("Failed to save the paired presenter: {:?}", e);
+            }
+        }
+
+        cx.schedule
+            .tick(cx.scheduled + TICK_CYCLES.cycles())
+            .unwrap();
+    }
+
+    // Interrupts RTIC takes over to run the software tasks
+    extern "C" {
+        fn EXTI0();
+    }
+};
+
+/// Carries on after a double ECC error of a flash read, which `flash::read`
+/// reports
+#[exception]
+fn NMI() {
+    if !flash::handle_nmi() {
+        panic!("NMI");
+    }
+}
 
-        led_cnt = led_cnt.wrapping_add(1);
+/// State of the host sent back to the presenters
+fn host_status(app: &App, hop_channel: Option<u8>) -> Status {
+    let mut flags = StatusFlags::empty();
+    flags.set(StatusFlags::USB_CONFIGURED, usb_power::is_configured());
+    flags.set(StatusFlags::USB_SUSPENDED, usb_power::is_suspended());
+    flags.set(StatusFlags::TIMER_RUNNING, app.slide_timer().is_running());
+    Status {
+        flags,
+        keyboard_leds: app.keyboard_leds().bits(),
+        timer_seconds: app.slide_timer().seconds(),
+        hop_channel,
     }
 }
 
-#[interrupt]
-fn OTG_FS() {
-    let mut buf = [0u8; 64];
-    free(|cs| {
-        let mut usb_dev_ref = USB_DEV.borrow(cs).borrow_mut();
-        let usb_dev = usb_dev_ref.as_mut().unwrap();
-        let mut usb_hid_cursor_ref = USB_HID_CURSOR.borrow(cs).borrow_mut();
-        let usb_hid_cursor = usb_hid_cursor_ref.as_mut().unwrap();
-        let mut usb_hid_mouse_ref = USB_HID_MOUSE.borrow(cs).borrow_mut();
-        let usb_hid_mouse = usb_hid_mouse_ref.as_mut().unwrap();
-        let mut usb_hid_kbd_ref = USB_HID_KBD.borrow(cs).borrow_mut();
-        let usb_hid_kbd = usb_hid_kbd_ref.as_mut().unwrap();
-        let mut usb_ser_ref = USB_SER.borrow(cs).borrow_mut();
-        let usb_ser = usb_ser_ref.as_mut().unwrap();
-        let mut serial_buf_ref = SERIAL_BUF.borrow(cs).borrow_mut();
-        let serial_buf = serial_buf_ref.as_mut().unwrap();
-
-        if usb_dev.poll(&mut [usb_ser, usb_hid_cursor, usb_hid_mouse, usb_hid_kbd]) {
-            if let Ok(len) = usb_ser.read(&mut buf) {
-                serial_buf.feed(&buf[..len]).ok();
+/// Runs the commands of a wireless packet, returning whether it is
+/// authentic
+fn process_packet<H, R>(
+    app: &mut App,
+    res: &mut Shared<'_, H, R>,
+    hopper: &mut Hopper,
+    pipe: u8,
+    data: &[u8],
+) -> bool
+where
+    H: Mutex<T = HidClasses>,
+    R: Mutex<T = NRF24Mode<NRF24Device>>,
+{
+    LinkStats::add(&LINK_STATS.packets, 1);
+    let mut buf = [0u8; MAX_PACKET_LEN];
+    let config = &mut *res.config;
+    let save_floor = |pipe: u8, floor| {
+        config.counter_floor[usize::from(pipe)] = floor;
+        match config.save_counter_floors() {
+            Ok(()) => true,
+            Err(e) => {
+                error!("Failed to save the counter floor: {:?}", e);
+                false
             }
         }
+    };
+    let data = match res.secure_link.open(pipe, data, &mut buf, save_floor) {
+        Ok(data) => data,
+        Err(AuthError::Duplicate) => {
+            debug!("Duplicate packet on pipe {}", pipe);
+            LinkStats::add(&LINK_STATS.duplicates, 1);
+            return true;
+        }
+        Err(e) => {
+            warn!("Rejected wireless packet on pipe {}: {}", pipe, e);
+            LinkStats::add(&LINK_STATS.rejected, 1);
+            return false;
+        }
+    };
+    // Any packet of the presenter shows it is still in range
+    app.heard(pipe);
+
+    match Packet::decode(data) {
+        Ok(Packet::Text(s)) => {
+            debug!("Wireless command on pipe {}: {:?}", pipe, s);
+            match s.parse::<Commands>() {
+                Ok(cmd) => {
+                    debug!("Parsed command: {:?}", cmd);
+                    if let Err(e) = app.process_cmd(res, Source::Pipe(pipe), cmd) {
+                        debug!("Wireless command failed: {}", e);
+                    }
+                }
+                Err(e) => debug!("Invalid wireless command: {}", e),
+            }
+        }
+        Ok(Packet::Frame(frame)) => {
+            debug!(
+                "Wireless frame on pipe {}: {:?} #{}",
+                pipe, frame.opcode, frame.seq
+            );
+            if !res.seq_tracker.accept(pipe, frame.seq, frame.restart) {
+                return true;
+            }
+            if let Some(channel) = frame.hop_confirm() {
+                hopper.confirmed(pipe, channel);
+            }
+            for cmd in frame.commands() {
+                match cmd {
+                    Ok(cmd) => {
+                        debug!("Parsed command: {:?}", cmd);
+                        if let Err(e) = app.process_cmd(res, Source::Pipe(pipe), cmd) {
+                            debug!("Wireless command failed: {}", e);
+                        }
+                    }
+                    Err(e) => debug!("Invalid wireless frame: {}", e),
+                }
+            }
+        }
+        Err(e) => debug!("Invalid wireless packet: {}", e),
+    }
+    true
+}
 
-        if let Ok(cmdline) = serial_buf.get_line(&mut buf) {
-            drop(usb_ser_ref);
-            debug!("Serial command: {:?}", cmdline.trim_end());
-            if let Ok(cmd) = cmdline.trim_end().parse::<Commands>() {
-                debug!("Parsed command: {:?}", cmd);
-                SERIAL_CMD.borrow(cs).replace(Some(cmd));
+/// Parses a serial command line into `SERIAL_QUEUE`, returning whether a
+/// request was queued
+fn queue_line(line: Result<&str, LineError<'_>>) -> bool {
+    let cmdline = match line {
+        Ok(cmdline) => cmdline.trim(),
+        Err(e) => {
+            let err = match e {
+                LineError::TooLong(_) => ParseError::LineTooLong,
+                LineError::InvalidUtf8(_) => ParseError::InvalidUtf8,
             };
+            serial::reply_err(Request::parse_seq(e.start()), err.code(), &err);
+            return false;
         }
-    });
+    };
+    // Ignore blank lines, e.g. the '\n' of a "\r\n" line ending
+    if cmdline.is_empty() {
+        return false;
+    }
+    debug!("Serial command: {:?}", cmdline);
+    match Request::parse(cmdline) {
+        (_, Ok(req)) => {
+            debug!("Parsed command: {:?}", req);
+            if let Err(req) = SERIAL_QUEUE.enqueue(req) {
+                let e = ExecError::QueueFull;
+                serial::reply_err(req.seq, e.code(), &e);
+                return false;
+            }
+            true
+        }
+        (seq, Err(e)) => {
+            serial::reply_err(seq, e.code(), &e);
+            false
+        }
+    }
+}
+
+/// Acknowledges a serial command once it has finished
+fn reply(seq: Option<u16>, result: Result<(), ExecError>) {
+    match (seq, result) {
+        (Some(seq), Ok(())) => serial::reply_ok(seq),
+        (seq, Err(e)) => serial::reply_err(seq, e.code(), &e),
+        (None, Ok(())) => (),
+    }
 }
@@ -1,9 +1,10 @@
 use core::{
     convert::Infallible,
     mem::{replace, zeroed},
+    ptr,
 };
 use embedded_nrf24l01::{Configuration, Device, RxMode, StandbyMode, TxMode, NRF24L01};
-use stm32l4::stm32l4x6::SPI1;
+use stm32l4::stm32l4x6::{GPIOB, SPI1};
 use stm32l4xx_hal::{gpio::*, spi::Spi};
 
 pub type NRF24Device = NRF24L01<
@@ -121,3 +122,82 @@ impl<D: Device> NRF24Mode<D> {
         }
     }
 }
+
+/// nRF24 registers and commands which `embedded_nrf24l01` doesn't offer
+pub mod reg {
+    pub const CONFIG: u8 = 0x00;
+    pub const SETUP_RETR: u8 = 0x04;
+    pub const RF_CH: u8 = 0x05;
+    pub const RF_SETUP: u8 = 0x06;
+    pub const RPD: u8 = 0x09;
+    /// RX_ADDR_P0, followed by the ones of pipes 1 to 5
+    pub const RX_ADDR_P0: u8 = 0x0A;
+    pub const FIFO_STATUS: u8 = 0x17;
+    pub const FEATURE: u8 = 0x1D;
+
+    /// RX_FULL of FIFO_STATUS
+    pub const FIFO_RX_FULL: u8 = 1 << 1;
+    /// TX_FULL of FIFO_STATUS
+    pub const FIFO_TX_FULL: u8 = 1 << 5;
+    /// EN_ACK_PAY of FEATURE
+    pub const FEATURE_EN_ACK_PAY: u8 = 1 << 1;
+    /// EN_DPL of FEATURE
+    pub const FEATURE_EN_DPL: u8 = 1 << 2;
+
+    pub(super) const R_REGISTER: u8 = 0x00;
+    pub(super) const W_REGISTER: u8 = 0x20;
+    pub(super) const W_ACK_PAYLOAD: u8 = 0xA8;
+}
+
+/// Raw SPI access to the nRF24, for what the driver can't do
+///
+/// Borrowing the radio mutably keeps the driver off the bus meanwhile.
+impl NRF24Mode<NRF24Device> {
+    pub fn read_register(&mut self, reg: u8) -> u8 {
+        let mut buf = [reg::R_REGISTER | reg, 0];
+        self.transfer(&mut buf);
+        buf[1]
+    }
+
+    /// Reads a multi-byte register, e.g. an address, LSB first
+    pub fn read_registers(&mut self, reg: u8, values: &mut [u8]) {
+        let mut buf = [0u8; 6];
+        let len = values.len().min(5);
+        buf[0] = reg::R_REGISTER | reg;
+        self.transfer(&mut buf[..=len]);
+        values[..len].copy_from_slice(&buf[1..=len]);
+    }
+
+    pub fn write_register(&mut self, reg: u8, value: u8) {
+        self.transfer(&mut [reg::W_REGISTER | reg, value]);
+    }
+
+    /// Queues a payload for the next ACK sent on the pipe
+    ///
+    /// Needs `reg::FEATURE_EN_ACK_PAY`, and the payload may take one of the
+    /// 3 TX FIFO slots until then.
+    pub fn write_ack_payload(&mut self, pipe: u8, payload: &[u8]) {
+        let mut buf = [0u8; 33];
+        let len = payload.len().min(32);
+        buf[0] = reg::W_ACK_PAYLOAD | (pipe & 0x07);
+        buf[1..=len].copy_from_slice(&payload[..len]);
+        self.transfer(&mut buf[..=len]);
+    }
+
+    /// Sends `buf` with CSN low, replacing it with the bytes read back
+    fn transfer(&mut self, buf: &mut [u8]) {
+        let spi = unsafe { &(*SPI1::ptr()) };
+        let gpiob = unsafe { &(*GPIOB::ptr()) };
+        // CSN is PB8
+        gpiob.bsrr.write(|w| w.br8().set_bit());
+        for byte in buf.iter_mut() {
+            while spi.sr.read().txe().bit_is_clear() {}
+            // Safety: 8 bit accesses keep the FIFO from packing two frames
+            unsafe { ptr::write_volatile(&spi.dr as *const _ as *mut u8, *byte) };
+            while spi.sr.read().rxne().bit_is_clear() {}
+            *byte = unsafe { ptr::read_volatile(&spi.dr as *const _ as *const u8) };
+        }
+        while spi.sr.read().bsy().bit_is_set() {}
+        gpiob.bsrr.write(|w| w.bs8().set_bit());
+    }
+}
@@ -0,0 +1,394 @@
+//! Wireless packet decoding
+//!
+//! A packet is either a text command, as accepted over serial, or a binary
+//! frame, told apart by the first byte: text starts with a printable ASCII
+//! character and a frame with its version, which is a control character.
+//!
+//! Frame layout, multi-byte fields in little endian:
+//!
+//! | Offset | Size | Field                              |
+//! |--------|------|------------------------------------|
+//! | 0      | 1    | Version, `FRAME_VERSION`           |
+//! | 1      | 1    | Opcode, with `RESTART_FLAG`        |
+//! | 2      | 1    | Sequence number                    |
+//! | 3      | ..   | Opcode payload                     |
+//!
+//! Frames from a transmitter with a key arrive wrapped by `secure`, which
+//! unwraps them before they are decoded here.
+//!
+//! The payload of `Opcode::Commands` is a list of commands, each a tag byte
+//! followed by the arguments of that tag, see `CommandTag`.
+//!
+//! While a presenter holds something down, it sends a packet at least every
+//! `KEEP_ALIVE_MS`, such as the same `kd` again. The receiver releases what
+//! a presenter holds once it is silent for the `release` setting, so that
+//! must be longer than `KEEP_ALIVE_MS`.
+
+use crate::command::{Chord, Commands, Text, TimerAction, CONSUMER_USAGE_MAX, SYSTEM_NAMES};
+use crate::hid_report::{KeyboardModifiers, MouseButtons};
+use crate::layout::LayoutId;
+use core::{convert::TryFrom, fmt, str};
+
+/// Version of the frame layout decoded here
+pub const FRAME_VERSION: u8 = 1;
+/// Length of the frame header
+pub const HEADER_LEN: usize = 3;
+/// Set in the opcode byte until a transmitter which has just started gets
+/// its first ACK, so its sequence numbers start over, see `SeqTracker`
+pub const RESTART_FLAG: u8 = 0x80;
+/// Longest a presenter holding something down stays silent, in milliseconds
+pub const KEEP_ALIVE_MS: u16 = 1_000;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Opcode {
+    /// Payload is a list of commands to run in order
+    Commands = 0x01,
+    /// Sent by a presenter in pairing mode, without payload
+    PairRequest = 0x02,
+    /// Answer to `PairRequest`, see `pairing`
+    PairResponse = 0x03,
+    /// Receiver state sent back in ACK payloads, see `ack`
+    Status = 0x04,
+    /// Confirms the channel announced with `StatusFlags::HOP`, which is the
+    /// payload, see `hopping`
+    HopConfirm = 0x05,
+}
+
+impl TryFrom<u8> for Opcode {
+    type Error = FrameError;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0x01 => Ok(Opcode::Commands),
+            0x02 => Ok(Opcode::PairRequest),
+            0x03 => Ok(Opcode::PairResponse),
+            0x04 => Ok(Opcode::Status),
+            0x05 => Ok(Opcode::HopConfirm),
+            _ => Err(FrameError::UnknownOpcode(value)),
+        }
+    }
+}
+
+/// Tags of the commands packed in a `Opcode::Commands` payload
+///
+/// | Tag  | Command   | Arguments                             |
+/// |------|-----------|---------------------------------------|
+/// | 0x01 | `ma`      | x: u16, y: u16                        |
+/// | 0x02 | `mr`      | x: i16, y: i16                        |
+/// | 0x03 | `md`      | buttons: u8                           |
+/// | 0x04 | `mu`      | buttons: u8                           |
+/// | 0x05 | `kd`      | key: u8                               |
+/// | 0x06 | `ku`      | key: u8                               |
+/// | 0x07 | `wh`      | wheel: i8                             |
+/// | 0x08 | `kp`      | modifiers: u8, key: u8, hold_ms: u16  |
+/// | 0x09 | `cc down` | usage: u16                            |
+/// | 0x0A | `cc up`   | usage: u16                            |
+/// | 0x0B | `cc tap`  | usage: u16                            |
+/// | 0x0C | `sc down` | usage: u8                             |
+/// | 0x0D | `sc up`   | usage: u8                             |
+/// | 0x0E | `sc tap`  | usage: u8                             |
+/// | 0x0F | `type`    | length: u8, UTF-8 text                |
+/// | 0x10 | `layout`  | index in `LayoutId::ALL`: u8          |
+/// | 0x11 | `timer`   | 0 start, 1 stop, 2 reset: u8          |
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum CommandTag {
+    AbsMove = 0x01,
+    RelMove = 0x02,
+    MouseDown = 0x03,
+    MouseUp = 0x04,
+    KeyDown = 0x05,
+    KeyUp = 0x06,
+    Wheel = 0x07,
+    Tap = 0x08,
+    ConsumerDown = 0x09,
+    ConsumerUp = 0x0A,
+    ConsumerTap = 0x0B,
+    SystemDown = 0x0C,
+    SystemUp = 0x0D,
+    SystemTap = 0x0E,
+    Type = 0x0F,
+    SetLayout = 0x10,
+    Timer = 0x11,
+}
+
+impl CommandTag {
+    const ALL: [CommandTag; 17] = [
+        CommandTag::AbsMove,
+        CommandTag::RelMove,
+        CommandTag::MouseDown,
+        CommandTag::MouseUp,
+        CommandTag::KeyDown,
+        CommandTag::KeyUp,
+        CommandTag::Wheel,
+        CommandTag::Tap,
+        CommandTag::ConsumerDown,
+        CommandTag::ConsumerUp,
+        CommandTag::ConsumerTap,
+        CommandTag::SystemDown,
+        CommandTag::SystemUp,
+        CommandTag::SystemTap,
+        CommandTag::Type,
+        CommandTag::SetLayout,
+        CommandTag::Timer,
+    ];
+
+    fn from_u8(value: u8) -> Option<Self> {
+        CommandTag::ALL
+            .iter()
+            .copied()
+            .find(|&tag| tag as u8 == value)
+    }
+}
+
+/// Reasons for a wireless packet failing to decode
+///
+/// Offsets are byte offsets in the packet.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FrameError {
+    Empty,
+    UnsupportedVersion(u8),
+    /// Shorter than `HEADER_LEN`
+    TruncatedHeader,
+    UnknownOpcode(u8),
+    UnknownCommand {
+        tag: u8,
+        offset: usize,
+    },
+    /// The command at the offset misses some of its arguments
+    TruncatedCommand(usize),
+    /// The command at the offset has an argument out of range
+    InvalidArgument(usize),
+    /// Text packets which are not UTF-8
+    InvalidText,
+}
+
+impl fmt::Display for FrameError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FrameError::Empty => f.write_str("empty packet"),
+            FrameError::UnsupportedVersion(v) => write!(f, "unsupported frame version {}", v),
+            FrameError::TruncatedHeader => f.write_str("truncated frame header"),
+            FrameError::UnknownOpcode(op) => write!(f, "unknown opcode {:#04x}", op),
+            FrameError::UnknownCommand { tag, offset } => {
+                write!(f, "unknown command tag {:#04x} at {}", tag, offset)
+            }
+            FrameError::TruncatedCommand(offset) => {
+                write!(f, "truncated command at {}", offset)
+            }
+            FrameError::InvalidArgument(offset) => {
+                write!(f, "command at {} has an invalid argument", offset)
+            }
+            FrameError::InvalidText => f.write_str("text packet is not UTF-8"),
+        }
+    }
+}
+
+/// A decoded wireless packet
+#[derive(Debug)]
+pub enum Packet<'a> {
+    /// A text command, with trailing whitespace trimmed
+    Text(&'a str),
+    Frame(Frame<'a>),
+}
+
+impl<'a> Packet<'a> {
+    pub fn decode(data: &'a [u8]) -> Result<Self, FrameError> {
+        match data.first() {
+            None => Err(FrameError::Empty),
+            Some(0x20..=0x7E) => str::from_utf8(data)
+                .map(|s| Packet::Text(s.trim_end()))
+                .map_err(|_| FrameError::InvalidText),
+            Some(&FRAME_VERSION) => Frame::decode(data).map(Packet::Frame),
+            Some(&version) => Err(FrameError::UnsupportedVersion(version)),
+        }
+    }
+}
+
+/// A binary packet with its header decoded
+#[derive(Debug)]
+pub struct Frame<'a> {
+    pub opcode: Opcode,
+    /// `RESTART_FLAG` was set
+    pub restart: bool,
+    pub seq: u8,
+    payload: &'a [u8],
+}
+
+impl<'a> Frame<'a> {
+    fn decode(data: &'a [u8]) -> Result<Self, FrameError> {
+        if data.len() < HEADER_LEN {
+            return Err(FrameError::TruncatedHeader);
+        }
+        Ok(Self {
+            opcode: Opcode::try_from(data[1] & !RESTART_FLAG)?,
+            restart: data[1] & RESTART_FLAG != 0,
+            seq: data[2],
+            payload: &data[HEADER_LEN..],
+        })
+    }
+
+    /// Decodes the commands of an `Opcode::Commands` frame one by one
+    ///
+    /// Decoding stops after the first error, since the length of a broken
+    /// command is unknown. Other frames have no commands.
+    pub fn commands(&self) -> FrameCommands<'a> {
+        let payload = match self.opcode {
+            Opcode::Commands => self.payload,
+            Opcode::PairRequest | Opcode::PairResponse | Opcode::Status | Opcode::HopConfirm => &[],
+        };
+        FrameCommands {
+            payload,
+            pos: 0,
+            failed: false,
+        }
+    }
+
+    /// Channel confirmed by an `Opcode::HopConfirm` frame
+    pub fn hop_confirm(&self) -> Option<u8> {
+        match (self.opcode, self.payload) {
+            (Opcode::HopConfirm, &[channel]) => Some(channel),
+            _ => None,
+        }
+    }
+}
+
+/// Iterator over the commands of a frame
+#[derive(Debug)]
+pub struct FrameCommands<'a> {
+    payload: &'a [u8],
+    pos: usize,
+    failed: bool,
+}
+
+impl<'a> FrameCommands<'a> {
+    /// Offset of the current command in the packet
+    fn offset(&self) -> usize {
+        HEADER_LEN + self.pos
+    }
+
+    fn decode_next(&mut self) -> Result<Commands, FrameError> {
+        let offset = self.offset();
+        let tag_byte = self.payload[self.pos];
+        let tag = CommandTag::from_u8(tag_byte).ok_or(FrameError::UnknownCommand {
+            tag: tag_byte,
+            offset,
+        })?;
+        let mut args = Reader {
+            data: &self.payload[self.pos + 1..],
+            offset,
+        };
+
+        let cmd = match tag {
+            CommandTag::AbsMove => Commands::AbsMove(args.u16()?, args.u16()?),
+            CommandTag::RelMove => Commands::RelMove(args.u16()? as i16, args.u16()? as i16),
+            CommandTag::MouseDown => Commands::MouseDown(args.buttons()?),
+            CommandTag::MouseUp => Commands::MouseUp(args.buttons()?),
+            CommandTag::KeyDown => Commands::KeyDown(args.u8()?),
+            CommandTag::KeyUp => Commands::KeyUp(args.u8()?),
+            CommandTag::Wheel => Commands::Wheel(args.u8()? as i8),
+            CommandTag::Tap => {
+                let modifiers = KeyboardModifiers::from_bits_truncate(args.u8()?);
+                let key = args.u8()?;
+                Commands::Tap(Chord {
+                    modifiers,
+                    keys: [key, 0, 0, 0, 0, 0],
+                    hold_ms: args.u16()?,
+                })
+            }
+            CommandTag::ConsumerDown => Commands::ConsumerDown(args.consumer_usage()?),
+            CommandTag::ConsumerUp => Commands::ConsumerUp(args.consumer_usage()?),
+            CommandTag::ConsumerTap => Commands::ConsumerTap(args.consumer_usage()?),
+            CommandTag::SystemDown => Commands::SystemDown(args.system_usage()?),
+            CommandTag::SystemUp => Commands::SystemUp(args.system_usage()?),
+            CommandTag::SystemTap => Commands::SystemTap(args.system_usage()?),
+            CommandTag::Type => {
+                let len = usize::from(args.u8()?);
+                let text = str::from_utf8(args.bytes(len)?)
+                    .ok()
+                    .and_then(Text::new)
+                    .ok_or(FrameError::InvalidArgument(offset))?;
+                Commands::Type(text)
+            }
+            CommandTag::SetLayout => {
+                let index = usize::from(args.u8()?);
+                let layout = LayoutId::ALL
+                    .get(index)
+                    .copied()
+                    .ok_or(FrameError::InvalidArgument(offset))?;
+                Commands::SetLayout(layout)
+            }
+            CommandTag::Timer => {
+                let action = match args.u8()? {
+                    0 => TimerAction::Start,
+                    1 => TimerAction::Stop,
+                    2 => TimerAction::Reset,
+                    _ => return Err(FrameError::InvalidArgument(offset)),
+                };
+                Commands::Timer(action)
+            }
+        };
+
+        self.pos = self.payload.len() - args.data.len();
+        Ok(cmd)
+    }
+}
+
+impl<'a> Iterator for FrameCommands<'a> {
+    type Item = Result<Commands, FrameError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed || self.pos >= self.payload.len() {
+            return None;
+        }
+        let result = self.decode_next();
+        self.failed = result.is_err();
+        Some(result)
+    }
+}
+
+/// Little endian argument reader of one command
+struct Reader<'a> {
+    data: &'a [u8],
+    /// Offset of the command, for errors
+    offset: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], FrameError> {
+        if self.data.len() < len {
+            return Err(FrameError::TruncatedCommand(self.offset));
+        }
+        let (bytes, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, FrameError> {
+        self.bytes(1).map(|b| b[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, FrameError> {
+        self.bytes(2).map(|b| u16::from_le_bytes([b[0], b[1]]))
+    }
+
+    fn buttons(&mut self) -> Result<MouseButtons, FrameError> {
+        let bits = self.u8()?;
+        MouseButtons::from_bits(bits).ok_or(FrameError::InvalidArgument(self.offset))
+    }
+
+    fn consumer_usage(&mut self) -> Result<u16, FrameError> {
+        let usage = self.u16()?;
+        if usage > CONSUMER_USAGE_MAX {
+            return Err(FrameError::InvalidArgument(self.offset));
+        }
+        Ok(usage)
+    }
+
+    fn system_usage(&mut self) -> Result<u8, FrameError> {
+        let usage = self.u8()?;
+        if !SYSTEM_NAMES.iter().any(|&(_, id)| id == usage) {
+            return Err(FrameError::InvalidArgument(self.offset));
+        }
+        Ok(usage)
+    }
+}
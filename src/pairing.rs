@@ -0,0 +1,255 @@
+//! Binding a presenter to this receiver
+//!
+//! Holding the user button for `HOLD_MS` starts pairing mode, which listens
+//! on the well-known `PAIRING_ADDRESS`:
+//!
+//! 1. The presenter sends an `Opcode::PairRequest` frame.
+//! 2. The receiver picks a random address and key, and answers with an
+//!    `Opcode::PairResponse` frame whose payload is the address followed by
+//!    the key. The answer is sent again until the presenter ACKs it.
+//! 3. Both switch to the new address, and the receiver only accepts packets
+//!    secured with the new key. The pair is saved in the config. Presenters
+//!    on pipes 1 to 5 need new keys too, as theirs derive from this one.
+//!
+//! The key is sent in plain text, so pairing should be done out of reach of
+//! other radios. Pairing mode ends after `TIMEOUT_MS` without a presenter.
+
+use crate::clock::Instant;
+use crate::nrf24_mode::{NRF24Device, NRF24Mode};
+use crate::packet::{Opcode, Packet, FRAME_VERSION};
+use crate::secure::{Key, KEY_LEN};
+use embedded_nrf24l01::Configuration;
+use stm32l4xx_hal::stm32::{rng, RCC, RNG};
+
+/// Address both sides use while pairing
+pub const PAIRING_ADDRESS: &[u8; 5] = b"\xb0\x13\x82\x91\x65";
+/// How long the user button is held to start pairing
+pub const HOLD_MS: u32 = 3000;
+/// How long to wait for a presenter
+const TIMEOUT_MS: u32 = 30_000;
+/// How long to resend an answer which is not ACKed
+const ANSWER_TIMEOUT_MS: u32 = 1000;
+/// How long the LED shows the result
+const RESULT_MS: u32 = 2000;
+/// LED half period while waiting for a presenter
+const BLINK_MS: u32 = 100;
+/// Status reads for each random word before giving up on the RNG
+const RNG_TRIES: u32 = 10_000;
+
+/// Address and key shared with a paired presenter
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PairedDevice {
+    pub address: [u8; 5],
+    pub key: Key,
+}
+
+impl PairedDevice {
+    fn random() -> Result<Self, RngError> {
+        let mut device = Self {
+            address: [0; 5],
+            key: [0; KEY_LEN],
+        };
+        random_bytes(&mut device.address)?;
+        random_bytes(&mut device.key)?;
+        Ok(device)
+    }
+}
+
+/// The RNG kept reporting seed or clock errors, or had no data in time
+#[derive(Clone, Copy, Debug)]
+struct RngError;
+
+/// Fills `buf` from the hardware random number generator
+fn random_bytes(buf: &mut [u8]) -> Result<(), RngError> {
+    let rcc = unsafe { &(*RCC::ptr()) };
+    rcc.ahb2enr.modify(|_, w| w.rngen().set_bit());
+    let rng = unsafe { &(*RNG::ptr()) };
+    // Clocked by the 48MHz PllQ output, shared with USB
+    rng.cr.modify(|_, w| w.rngen().set_bit());
+    let result = buf.chunks_mut(4).try_for_each(|chunk| {
+        let word = random_word(rng)?.to_le_bytes();
+        chunk.copy_from_slice(&word[..chunk.len()]);
+        Ok(())
+    });
+    rng.cr.modify(|_, w| w.rngen().clear_bit());
+    result
+}
+
+fn random_word(rng: &rng::RegisterBlock) -> Result<u32, RngError> {
+    for _ in 0..RNG_TRIES {
+        let sr = rng.sr.read();
+        if sr.secs().bit_is_set() || sr.cecs().bit_is_set() {
+            // Data read around an error is not random. Restarting the RNG
+            // draws a new seed.
+            rng.sr
+                .modify(|_, w| w.seis().clear_bit().ceis().clear_bit());
+            rng.cr.modify(|_, w| w.rngen().clear_bit());
+            rng.cr.modify(|_, w| w.rngen().set_bit());
+            continue;
+        }
+        if sr.drdy().bit_is_set() {
+            return Ok(rng.dr.read().bits());
+        }
+    }
+    Err(RngError)
+}
+
+/// Points pipe 0 and the TX address at `address` and starts receiving
+///
+/// Both FIFOs are flushed. Status ACK payloads left in the TX FIFO would
+/// otherwise go out to the new address, e.g. ahead of a pairing answer.
+pub fn listen_on(nrf24l01: &mut NRF24Mode<NRF24Device>, address: &[u8; 5]) {
+    let standby = nrf24l01.to_standby();
+    standby.set_rx_addr(0, address).ok();
+    standby.set_tx_addr(address).ok();
+    standby.flush_rx().ok();
+    standby.flush_tx().ok();
+    nrf24l01.to_rx();
+}
+
+#[derive(Debug)]
+enum State {
+    /// Waiting for a pairing request
+    Listening,
+    /// Sending the answer until the presenter ACKs it
+    Answering {
+        device: PairedDevice,
+        seq: u8,
+        sending: bool,
+        since: Instant,
+    },
+    /// Showing the result on the LED
+    Finished { paired: bool, since: Instant },
+}
+
+/// What the main loop has to do after `Pairing::poll`
+#[derive(Debug)]
+pub enum Progress {
+    Pending,
+    /// Use this pair from now on
+    Paired(PairedDevice),
+    /// Go back to the previous pair, after a timeout or an error
+    TimedOut,
+    /// Pairing mode is over
+    Finished,
+}
+
+#[derive(Debug)]
+pub struct Pairing {
+    state: State,
+    started_at: Instant,
+}
+
+impl Pairing {
+    pub fn start(nrf24l01: &mut NRF24Mode<NRF24Device>) -> Self {
+        info!("Pairing mode started");
+        listen_on(nrf24l01, PAIRING_ADDRESS);
+        Self {
+            state: State::Listening,
+            started_at: Instant::now(),
+        }
+    }
+
+    pub fn poll(&mut self, nrf24l01: &mut NRF24Mode<NRF24Device>) -> Progress {
+        match &mut self.state {
+            State::Listening => {
+                if self.started_at.elapsed_ms() >= TIMEOUT_MS {
+                    warn!("Pairing timed out");
+                    self.finish(false);
+                    return Progress::TimedOut;
+                }
+
+                nrf24l01.configuration_mut().clear_interrupts().ok();
+                let nrf24l01_rx = nrf24l01.to_rx();
+                while nrf24l01_rx.can_read().unwrap().is_some() {
+                    let packet = nrf24l01_rx.read().unwrap();
+                    match Packet::decode(packet.as_ref()) {
+                        Ok(Packet::Frame(frame)) if frame.opcode == Opcode::PairRequest => {
+                            info!("Pairing request received");
+                            let device = match PairedDevice::random() {
+                                Ok(device) => device,
+                                Err(e) => {
+                                    error!("No random address and key: {:?}", e);
+                                    self.finish(false);
+                                    return Progress::TimedOut;
+                                }
+                            };
+                            self.state = State::Answering {
+                                device,
+                                seq: frame.seq,
+                                sending: false,
+                                since: Instant::now(),
+                            };
+                            break;
+                        }
+                        _ => debug!("Ignored packet while pairing"),
+                    }
+                }
+                Progress::Pending
+            }
+            State::Answering {
+                device,
+                seq,
+                sending,
+                since,
+            } => {
+                if since.elapsed_ms() >= ANSWER_TIMEOUT_MS {
+                    warn!("Pairing answer not ACKed");
+                    nrf24l01.to_standby().flush_tx().ok();
+                    nrf24l01.to_rx();
+                    self.state = State::Listening;
+                    return Progress::Pending;
+                }
+
+                let nrf24l01_tx = nrf24l01.to_tx();
+                if !*sending {
+                    let mut answer = [0u8; 3 + 5 + KEY_LEN];
+                    answer[0] = FRAME_VERSION;
+                    answer[1] = Opcode::PairResponse as u8;
+                    answer[2] = *seq;
+                    answer[3..8].copy_from_slice(&device.address);
+                    answer[8..].copy_from_slice(&device.key);
+                    *sending = nrf24l01_tx.send(&answer).is_ok();
+                }
+                match nrf24l01_tx.poll_send() {
+                    Ok(true) => {
+                        info!("Paired");
+                        let device = *device;
+                        self.finish(true);
+                        Progress::Paired(device)
+                    }
+                    // Lost, so send it again
+                    Ok(false) => {
+                        *sending = false;
+                        Progress::Pending
+                    }
+                    Err(_) => Progress::Pending,
+                }
+            }
+            State::Finished { since, .. } => {
+                if since.elapsed_ms() >= RESULT_MS {
+                    Progress::Finished
+                } else {
+                    Progress::Pending
+                }
+            }
+        }
+    }
+
+    fn finish(&mut self, paired: bool) {
+        self.state = State::Finished {
+            paired,
+            since: Instant::now(),
+        };
+    }
+
+    /// LED state showing the progress: blinking while waiting, on while
+    /// answering, then on if paired or off if not
+    pub fn led_on(&self) -> bool {
+        match self.state {
+            State::Listening => (self.started_at.elapsed_ms() / BLINK_MS) % 2 == 0,
+            State::Answering { .. } => true,
+            State::Finished { paired, .. } => paired,
+        }
+    }
+}
@@ -0,0 +1,85 @@
+use core::{
+    cell::UnsafeCell,
+    mem::MaybeUninit,
+    sync::atomic::{AtomicU32, AtomicUsize, Ordering},
+};
+
+/// Bounded lock-free single-producer single-consumer ring buffer
+///
+/// One slot is kept free to tell a full queue from an empty one, so the queue
+/// holds at most `N - 1` items. Only one context may enqueue and only one
+/// context may dequeue at a time, e.g. an interrupt handler feeding the main
+/// loop.
+pub struct Queue<T, const N: usize> {
+    buf: UnsafeCell<MaybeUninit<[T; N]>>,
+    /// Index of the next item to dequeue
+    head: AtomicUsize,
+    /// Index of the next free slot
+    tail: AtomicUsize,
+    /// Items rejected because the queue was full
+    dropped: AtomicU32,
+}
+
+// Safety: `head` is only written by the consumer and `tail` only by the
+// producer, and a slot is never accessed by both sides at the same time.
+unsafe impl<T: Send, const N: usize> Sync for Queue<T, N> {}
+
+impl<T, const N: usize> Queue<T, N> {
+    pub const fn new() -> Self {
+        Self {
+            buf: UnsafeCell::new(MaybeUninit::uninit()),
+            head: AtomicUsize::new(0),
+            tail: AtomicUsize::new(0),
+            dropped: AtomicU32::new(0),
+        }
+    }
+
+    fn slot(&self, idx: usize) -> *mut T {
+        // Safety: `idx` is always kept below `N`
+        unsafe { (self.buf.get() as *mut T).add(idx) }
+    }
+
+    /// Pushes an item, giving it back if the queue is full
+    pub fn enqueue(&self, item: T) -> Result<(), T> {
+        let tail = self.tail.load(Ordering::Relaxed);
+        let next = (tail + 1) % N;
+        if next == self.head.load(Ordering::Acquire) {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+            return Err(item);
+        }
+
+        // Safety: The slot is outside of the consumer's readable range
+        unsafe { self.slot(tail).write(item) };
+        self.tail.store(next, Ordering::Release);
+        Ok(())
+    }
+
+    pub fn dequeue(&self) -> Option<T> {
+        let head = self.head.load(Ordering::Relaxed);
+        if head == self.tail.load(Ordering::Acquire) {
+            return None;
+        }
+
+        // Safety: The slot has been written by the producer and won't be
+        // touched again until `head` moves past it
+        let item = unsafe { self.slot(head).read() };
+        self.head.store((head + 1) % N, Ordering::Release);
+        Some(item)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.head.load(Ordering::Acquire) == self.tail.load(Ordering::Acquire)
+    }
+
+    /// Number of items which can still be enqueued
+    pub fn space(&self) -> usize {
+        let head = self.head.load(Ordering::Acquire);
+        let tail = self.tail.load(Ordering::Relaxed);
+        (head + N - tail - 1) % N
+    }
+
+    /// Number of items dropped since the last call
+    pub fn take_dropped(&self) -> u32 {
+        self.dropped.swap(0, Ordering::Relaxed)
+    }
+}
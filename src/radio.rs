@@ -0,0 +1,123 @@
+//! Changing nRF24 settings while running
+//!
+//! Settings are written in standby mode, then the radio goes back to RX.
+
+use crate::config::{write_hex, Config};
+use crate::link::PIPE_COUNT;
+use crate::nrf24_mode::{reg, NRF24Device, NRF24Mode};
+use crate::serial::BlockingSerialWriter;
+use core::fmt::Write;
+use embedded_nrf24l01::{Configuration, Device, StandbyMode};
+
+type RadioError = <NRF24Device as Device>::Error;
+
+/// Registers read back from the radio
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Registers {
+    /// CRC and interrupt mask
+    pub config: u8,
+    pub rf_ch: u8,
+    /// Data rate and TX power
+    pub rf_setup: u8,
+    pub setup_retr: u8,
+    /// Address width in bytes
+    pub setup_aw: u8,
+    pub en_aa: [bool; PIPE_COUNT],
+    pub en_rxaddr: [bool; PIPE_COUNT],
+    /// Address of each pipe, pipes 2 to 5 with the upper bytes of pipe 1
+    pub rx_addr: [[u8; 5]; PIPE_COUNT],
+}
+
+impl Registers {
+    /// Prints `RF <register>=<value>` over serial, the addresses on a
+    /// second line
+    pub fn print(&self) {
+        let mut writer = BlockingSerialWriter;
+        write!(
+            writer,
+            "RF CONFIG=0x{:02x} RF_CH={} RF_SETUP=0x{:02x} SETUP_RETR=0x{:02x} \
+             SETUP_AW={} EN_AA=0x{:02x} EN_RXADDR=0x{:02x}\r\n",
+            self.config,
+            self.rf_ch,
+            self.rf_setup,
+            self.setup_retr,
+            self.setup_aw,
+            pipe_bits(&self.en_aa),
+            pipe_bits(&self.en_rxaddr),
+        )
+        .ok();
+        writer.write_str("RF").ok();
+        for (pipe, address) in self.rx_addr.iter().enumerate() {
+            write!(writer, " RX_ADDR_P{}=", pipe).ok();
+            write_hex(&mut writer, address);
+        }
+        writer.write_str("\r\n").ok();
+    }
+}
+
+/// Writes the radio settings of `config` and reads the registers back
+pub fn apply(
+    nrf24l01: &mut NRF24Mode<NRF24Device>,
+    config: &Config,
+) -> Result<Registers, RadioError> {
+    let result = write_settings(nrf24l01.to_standby(), config);
+    nrf24l01.to_rx();
+    result?;
+    read(nrf24l01)
+}
+
+/// Reads the registers without leaving the current mode
+pub fn read(nrf24l01: &mut NRF24Mode<NRF24Device>) -> Result<Registers, RadioError> {
+    let configuration = nrf24l01.configuration_mut();
+    let rf_ch = configuration.get_frequency()?;
+    let setup_aw = configuration.get_address_width()?;
+    let en_aa = configuration.get_auto_ack()?;
+    let en_rxaddr = configuration.read_enabled_pipes()?;
+
+    let mut rx_addr = [[0u8; 5]; PIPE_COUNT];
+    nrf24l01.read_registers(reg::RX_ADDR_P0, &mut rx_addr[0]);
+    nrf24l01.read_registers(reg::RX_ADDR_P0 + 1, &mut rx_addr[1]);
+    for pipe in 2..PIPE_COUNT {
+        // Only the first byte is their own
+        rx_addr[pipe] = rx_addr[1];
+        rx_addr[pipe][0] = nrf24l01.read_register(reg::RX_ADDR_P0 + pipe as u8);
+    }
+    Ok(Registers {
+        config: nrf24l01.read_register(reg::CONFIG),
+        rf_ch,
+        rf_setup: nrf24l01.read_register(reg::RF_SETUP),
+        setup_retr: nrf24l01.read_register(reg::SETUP_RETR),
+        setup_aw,
+        en_aa,
+        en_rxaddr,
+        rx_addr,
+    })
+}
+
+/// Writes the radio settings of `config`, which must be done in standby
+pub fn write_settings<D: Device>(
+    standby: &mut StandbyMode<D>,
+    config: &Config,
+) -> Result<(), D::Error> {
+    standby.set_frequency(config.channel)?;
+    standby.set_rf(&config.rate.data_rate(), config.tx_power)?;
+    standby.set_crc(config.crc.crc_mode())?;
+    standby.set_auto_retransmit(config.retransmit.delay, config.retransmit.count)?;
+    let mut enabled = [false; PIPE_COUNT];
+    for (pipe, enabled) in enabled.iter_mut().enumerate() {
+        standby.set_rx_addr(pipe, &config.pipe_address(pipe as u8))?;
+        *enabled = pipe < usize::from(config.pipes);
+    }
+    standby.set_pipes_rx_enable(&enabled)?;
+    standby.set_tx_addr(&config.paired.address)?;
+    // Packets received under the old settings are stale
+    standby.flush_rx()?;
+    Ok(())
+}
+
+fn pipe_bits(pipes: &[bool; PIPE_COUNT]) -> u8 {
+    pipes
+        .iter()
+        .enumerate()
+        .fold(0, |bits, (pipe, &enabled)| bits | (enabled as u8) << pipe)
+}
@@ -0,0 +1,101 @@
+//! nRF24 reception in the EXTI9_5 interrupt
+//!
+//! The IRQ line of the nRF24 goes to PB7, which raises EXTI7 on its falling
+//! edge. Only RX_DR is unmasked in the nRF24, so the interrupt drains the RX
+//! FIFO into `RX_QUEUE` for the main loop. While the main loop drives the
+//! radio itself, e.g. for pairing, the interrupt leaves the FIFO alone.
+
+use crate::nrf24_mode::{reg, NRF24Device, NRF24Mode};
+use crate::queue::Queue;
+use crate::secure::MAX_PACKET_LEN;
+use core::sync::atomic::{AtomicBool, Ordering};
+use embedded_nrf24l01::Configuration;
+
+/// A packet read from the RX FIFO
+#[derive(Clone, Copy, Debug)]
+pub struct RxPacket {
+    pub pipe: u8,
+    len: u8,
+    data: [u8; MAX_PACKET_LEN],
+}
+
+impl AsRef<[u8]> for RxPacket {
+    fn as_ref(&self) -> &[u8] {
+        &self.data[..usize::from(self.len)]
+    }
+}
+
+pub static RX_QUEUE: Queue<RxPacket, 16> = Queue::new();
+
+/// The RX FIFO was found full since the last `take_rx_full`
+static RX_FULL: AtomicBool = AtomicBool::new(false);
+static PAUSED: AtomicBool = AtomicBool::new(false);
+
+/// Routes the IRQ pin to EXTI, on the falling edge
+pub fn listen() {
+    use stm32l4xx_hal::stm32::{EXTI, RCC, SYSCFG};
+
+    let rcc = unsafe { &(*RCC::ptr()) };
+    rcc.apb2enr.modify(|_, w| w.syscfgen().set_bit());
+    let syscfg = unsafe { &(*SYSCFG::ptr()) };
+    // Port B
+    syscfg
+        .exticr2
+        .modify(|_, w| unsafe { w.exti7().bits(0b001) });
+    let exti = unsafe { &(*EXTI::ptr()) };
+    exti.ftsr1.modify(|_, w| w.tr7().set_bit());
+    exti.imr1.modify(|_, w| w.mr7().set_bit());
+}
+
+pub fn clear_pending() {
+    use stm32l4xx_hal::stm32::EXTI;
+
+    let exti = unsafe { &(*EXTI::ptr()) };
+    exti.pr1.write(|w| w.pr7().set_bit());
+}
+
+/// Keeps the interrupt off the RX FIFO while the main loop reads it
+///
+/// The IRQ line stays low until its flags are cleared, so after resuming
+/// EXTI9_5 has to be pended by hand for packets which came meanwhile.
+pub fn pause(paused: bool) {
+    PAUSED.store(paused, Ordering::Relaxed);
+}
+
+/// Whether the RX FIFO was found full since the last call
+pub fn take_rx_full() -> bool {
+    RX_FULL.swap(false, Ordering::Relaxed)
+}
+
+/// Moves the packets of the RX FIFO to `RX_QUEUE`
+pub fn drain(nrf24l01: &mut NRF24Mode<NRF24Device>) {
+    if PAUSED.load(Ordering::Relaxed) {
+        return;
+    }
+    // Sampled before reading, which clears RX_FULL
+    if nrf24l01.read_register(reg::FIFO_STATUS) & reg::FIFO_RX_FULL != 0 {
+        RX_FULL.store(true, Ordering::Relaxed);
+    }
+    // Clear RX_DR first, so a packet arriving while reading raises it again
+    nrf24l01.configuration_mut().clear_interrupts().ok();
+
+    let nrf24l01_rx = match nrf24l01.rx_ref() {
+        Some(nrf24l01_rx) => nrf24l01_rx,
+        None => return,
+    };
+    while let Ok(Some(pipe)) = nrf24l01_rx.can_read() {
+        let payload = match nrf24l01_rx.read() {
+            Ok(payload) => payload,
+            Err(_) => break,
+        };
+        let payload = payload.as_ref();
+        let mut packet = RxPacket {
+            pipe,
+            len: payload.len() as u8,
+            data: [0; MAX_PACKET_LEN],
+        };
+        packet.data[..payload.len()].copy_from_slice(payload);
+        // Dropped packets are counted by the queue
+        RX_QUEUE.enqueue(packet).ok();
+    }
+}
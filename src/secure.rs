@@ -0,0 +1,254 @@
+//! Authenticated encryption of wireless packets
+//!
+//! A secured packet wraps a whole binary frame, encrypted and authenticated
+//! with AES-128-CCM under the key of its transmitter:
+//!
+//! | Offset | Size | Field                                  |
+//! |--------|------|----------------------------------------|
+//! | 0      | 1    | `SECURE_VERSION`                       |
+//! | 1      | 4    | Counter, little endian                 |
+//! | 5      | ..   | Encrypted frame                        |
+//! | end-8  | 8    | Tag                                    |
+//!
+//! The first 5 bytes are authenticated too. The counter must grow with every
+//! packet, so the 13 byte nonce is the counter followed by zeros and a
+//! recorded packet is rejected when played again.
+//!
+//! So that this holds across a reset, the config keeps a counter floor for
+//! each pipe above every counter accepted so far. A counter reaching the
+//! floor first has a floor `COUNTER_BLOCK` or more above it saved, so flash
+//! is written about once every `COUNTER_BLOCK` packets. After a reset the
+//! counters below the floor are rejected, and the status frame in the ACK
+//! tells the presenter the lowest counter taken, see `ack`. A new key starts
+//! the counters over, so a key shouldn't be used again.
+//!
+//! Every pipe has a key, and packets on a pipe without one are rejected.
+//! Pipe 0 uses the key of the paired presenter, the other pipes keys derived
+//! from it by `pipe_key`. Presenters on those pipes share the secret of the
+//! receiver, so they could forge each other's packets, but nobody without it
+//! can.
+
+use crate::link::PIPE_COUNT;
+use aes::{Aes128, BlockCipher, NewBlockCipher};
+use ccm::aead::{generic_array::GenericArray, AeadInPlace, NewAead};
+use ccm::consts::{U13, U8};
+use ccm::Ccm;
+use core::fmt;
+
+/// Version byte of secured packets, after the plain `FRAME_VERSION`
+pub const SECURE_VERSION: u8 = 2;
+pub const KEY_LEN: usize = 16;
+/// Length of the version and counter
+const HEADER_LEN: usize = 5;
+const TAG_LEN: usize = 8;
+const NONCE_LEN: usize = 13;
+/// Largest nRF24 payload
+pub const MAX_PACKET_LEN: usize = 32;
+/// Counters reserved by each saved counter floor
+pub const COUNTER_BLOCK: u32 = 1 << 20;
+
+pub type Key = [u8; KEY_LEN];
+type Aes128Ccm = Ccm<Aes128, U8, U13>;
+
+/// Reasons for rejecting a wireless packet before running it
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AuthError {
+    /// The pipe has no key, so nothing on it can be trusted
+    NoKey,
+    /// A plain packet
+    NotSecured,
+    Truncated,
+    /// An authentic packet with the last accepted counter, i.e. an nRF24
+    /// retransmit after a lost ACK
+    Duplicate,
+    /// The counter is below the last accepted one
+    Replayed {
+        counter: u32,
+        last: u32,
+    },
+    /// The tag doesn't match, so the packet was forged, corrupted or is
+    /// from a transmitter with another key
+    BadTag,
+    /// No floor above the counter could be saved
+    FloorNotSaved,
+    /// The counter is too close to the end for a floor above it, so the
+    /// pipe needs a new key
+    CounterExhausted,
+}
+
+impl fmt::Display for AuthError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AuthError::NoKey => f.write_str("pipe has no key"),
+            AuthError::NotSecured => f.write_str("packet is not secured"),
+            AuthError::Truncated => f.write_str("truncated secured packet"),
+            AuthError::Duplicate => f.write_str("duplicate packet"),
+            AuthError::Replayed { counter, last } => {
+                write!(f, "replayed counter {}, last was {}", counter, last)
+            }
+            AuthError::BadTag => f.write_str("authentication failed"),
+            AuthError::FloorNotSaved => f.write_str("counter floor not saved"),
+            AuthError::CounterExhausted => f.write_str("counter exhausted"),
+        }
+    }
+}
+
+/// Key and counters of a pipe
+#[derive(Clone, Copy, Debug)]
+struct Peer {
+    key: Option<Key>,
+    last_counter: Option<u32>,
+    /// Saved counter floor, above every accepted counter
+    floor: u32,
+}
+
+impl Peer {
+    const UNKEYED: Peer = Peer {
+        key: None,
+        last_counter: None,
+        floor: 0,
+    };
+}
+
+/// Checks and decrypts the packets of each pipe
+#[derive(Debug)]
+pub struct SecureLink {
+    peers: [Peer; PIPE_COUNT],
+}
+
+impl SecureLink {
+    pub const fn new() -> Self {
+        Self {
+            peers: [Peer::UNKEYED; PIPE_COUNT],
+        }
+    }
+
+    /// Keys every pipe from the key of the receiver, see `pipe_key`
+    pub fn set_keys(&mut self, key: &Key) {
+        for pipe in 0..PIPE_COUNT as u8 {
+            self.set_key(pipe, &pipe_key(key, pipe));
+        }
+    }
+
+    /// Requires secured packets on the pipe, under the given key. The
+    /// counters start over when the key changes.
+    pub fn set_key(&mut self, pipe: u8, key: &Key) {
+        if let Some(peer) = self.peers.get_mut(usize::from(pipe)) {
+            if peer.key != Some(*key) {
+                *peer = Peer {
+                    key: Some(*key),
+                    ..Peer::UNKEYED
+                };
+            }
+        }
+    }
+
+    /// Rejects the counters below the floors saved in the config
+    pub fn set_floors(&mut self, floors: &[u32; PIPE_COUNT]) {
+        for (peer, &floor) in self.peers.iter_mut().zip(floors) {
+            peer.floor = floor;
+            peer.last_counter = floor.checked_sub(1);
+        }
+    }
+
+    /// Counter floors to be saved in the config
+    pub fn floors(&self) -> [u32; PIPE_COUNT] {
+        let mut floors = [0; PIPE_COUNT];
+        for (floor, peer) in floors.iter_mut().zip(&self.peers) {
+            *floor = peer.floor;
+        }
+        floors
+    }
+
+    /// Lowest counter the pipe would accept
+    pub fn next_counter(&self, pipe: u8) -> u32 {
+        self.peers
+            .get(usize::from(pipe))
+            .and_then(|peer| peer.last_counter)
+            .map_or(0, |last| last.saturating_add(1))
+    }
+
+    /// Returns the plain packet, decrypted into `buf`
+    ///
+    /// An authentic counter reaching the floor of the pipe is only accepted
+    /// once `save_floor` has saved a new floor for the pipe.
+    pub fn open<'a>(
+        &mut self,
+        pipe: u8,
+        data: &'a [u8],
+        buf: &'a mut [u8; MAX_PACKET_LEN],
+        save_floor: impl FnOnce(u8, u32) -> bool,
+    ) -> Result<&'a [u8], AuthError> {
+        let peer = match self.peers.get_mut(usize::from(pipe)) {
+            Some(peer) => peer,
+            None => return Err(AuthError::NoKey),
+        };
+        let key = peer.key.ok_or(AuthError::NoKey)?;
+
+        if data.first() != Some(&SECURE_VERSION) {
+            return Err(AuthError::NotSecured);
+        }
+        if data.len() < HEADER_LEN + TAG_LEN || data.len() > MAX_PACKET_LEN {
+            return Err(AuthError::Truncated);
+        }
+
+        let (header, rest) = data.split_at(HEADER_LEN);
+        let (ciphertext, tag) = rest.split_at(rest.len() - TAG_LEN);
+        let counter = u32::from_le_bytes([header[1], header[2], header[3], header[4]]);
+        if let Some(last) = peer.last_counter {
+            if counter < last {
+                return Err(AuthError::Replayed { counter, last });
+            }
+        }
+
+        let mut nonce = [0u8; NONCE_LEN];
+        nonce[..4].copy_from_slice(&header[1..]);
+        let plain = &mut buf[..ciphertext.len()];
+        plain.copy_from_slice(ciphertext);
+        Aes128Ccm::new(GenericArray::from_slice(&key))
+            .decrypt_in_place_detached(
+                GenericArray::from_slice(&nonce),
+                header,
+                plain,
+                GenericArray::from_slice(tag),
+            )
+            .map_err(|_| AuthError::BadTag)?;
+        // Checked after the tag, so forged packets don't pass as duplicates
+        if peer.last_counter == Some(counter) {
+            return Err(AuthError::Duplicate);
+        }
+
+        // Only authentic packets may move the counter forward, so forged
+        // ones can't wear the flash out
+        if counter >= peer.floor {
+            let floor = next_floor(counter).ok_or(AuthError::CounterExhausted)?;
+            if !save_floor(pipe, floor) {
+                return Err(AuthError::FloorNotSaved);
+            }
+            peer.floor = floor;
+        }
+        peer.last_counter = Some(counter);
+        Ok(plain)
+    }
+}
+
+/// Floor saved for a counter reaching the last one, at least
+/// `COUNTER_BLOCK` above it
+fn next_floor(counter: u32) -> Option<u32> {
+    (counter / COUNTER_BLOCK + 2).checked_mul(COUNTER_BLOCK)
+}
+
+/// Key of a pipe, derived from the key of the receiver
+///
+/// Pipe 0 uses the key as is. The key of another pipe is the AES encryption
+/// of a block holding `b'P'` and the pipe number, under the receiver key.
+pub fn pipe_key(key: &Key, pipe: u8) -> Key {
+    if pipe == 0 {
+        return *key;
+    }
+    let mut block = GenericArray::clone_from_slice(&[0u8; KEY_LEN]);
+    block[0] = b'P';
+    block[1] = pipe;
+    Aes128::new(GenericArray::from_slice(key)).encrypt_block(&mut block);
+    block.into()
+}
@@ -0,0 +1,134 @@
+use crate::queue::Queue;
+use core::fmt::{self, Write};
+use cortex_m::{asm, interrupt::free, peripheral::NVIC};
+use stm32l4xx_hal::stm32::Interrupt;
+use usb_device::class_prelude::UsbBus;
+use usbd_serial::SerialPort;
+
+/// Output for the USB CDC serial port, sent out by the `OTG_FS` task
+static SERIAL_TX: Queue<u8, 512> = Queue::new();
+
+/// Queues output, all of it or nothing
+///
+/// Writers of every priority share the queue, so they take turns in a
+/// critical section.
+fn enqueue(data: &[u8]) -> bool {
+    let queued = free(|_| {
+        if SERIAL_TX.space() < data.len() {
+            return false;
+        }
+        for &byte in data {
+            SERIAL_TX.enqueue(byte).ok();
+        }
+        true
+    });
+    // Get the USB task to send it out
+    NVIC::pend(Interrupt::OTG_FS);
+    queued
+}
+
+/// Writer for the USB CDC serial port
+///
+/// Shared by the logger and the command replies sent back to the host.
+#[derive(Clone, Debug)]
+pub struct SerialWriter;
+
+impl Write for SerialWriter {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        if enqueue(s.as_bytes()) {
+            Ok(())
+        } else {
+            Err(fmt::Error)
+        }
+    }
+}
+
+/// Writer which waits for the host to drain the port instead of dropping data
+///
+/// Used for long command output. It must not be used from the `OTG_FS`
+/// task, since that is where the port gets drained.
+#[derive(Clone, Debug)]
+pub struct BlockingSerialWriter;
+
+impl BlockingSerialWriter {
+    /// Attempts before giving up on a host which doesn't read the port
+    const MAX_RETRIES: u32 = 10_000;
+}
+
+impl Write for BlockingSerialWriter {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for chunk in s.as_bytes().chunks(64) {
+            let mut retries = 0;
+            while !enqueue(chunk) {
+                retries += 1;
+                if retries > Self::MAX_RETRIES {
+                    return Err(fmt::Error);
+                }
+                // Let the USB task send out the queued data
+                asm::delay(1000);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Output taken from `SERIAL_TX` which the port hasn't accepted yet
+#[derive(Debug)]
+pub struct SerialTx {
+    buf: [u8; 64],
+    start: usize,
+    end: usize,
+}
+
+impl SerialTx {
+    pub const fn new() -> Self {
+        Self {
+            buf: [0; 64],
+            start: 0,
+            end: 0,
+        }
+    }
+
+    /// Writes queued output to the port until it is full
+    pub fn drain<B: UsbBus>(&mut self, port: &mut SerialPort<B>) {
+        loop {
+            if self.start == self.end {
+                self.start = 0;
+                self.end = 0;
+                while self.end < self.buf.len() {
+                    match SERIAL_TX.dequeue() {
+                        Some(byte) => self.buf[self.end] = byte,
+                        None => break,
+                    }
+                    self.end += 1;
+                }
+                if self.end == 0 {
+                    return;
+                }
+            }
+
+            match port.write(&self.buf[self.start..self.end]) {
+                Ok(len) if len > 0 => self.start += len,
+                // Continued once the host has read the port
+                _ => return,
+            }
+        }
+    }
+}
+
+/// Acknowledges a successfully executed command
+pub fn reply_ok(seq: u16) {
+    write!(SerialWriter, "OK {}\r\n", seq).ok();
+}
+
+/// Reports a failed command, tagged with its sequence id if it has one
+///
+/// Untagged errors get `-` in place of the id, so their code can't be taken
+/// for the id of a pending command.
+pub fn reply_err(seq: Option<u16>, code: u8, detail: &dyn fmt::Display) {
+    if let Some(seq) = seq {
+        write!(SerialWriter, "ERR {} {} {}\r\n", seq, code, detail).ok();
+    } else {
+        write!(SerialWriter, "ERR - {} {}\r\n", code, detail).ok();
+    }
+}
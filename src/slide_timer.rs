@@ -0,0 +1,56 @@
+//! Time since the talk started, shown on the presenter
+
+use crate::clock::Instant;
+
+#[derive(Debug)]
+pub struct SlideTimer {
+    seconds: u32,
+    /// Start of the second being counted, while running
+    tick: Option<Instant>,
+}
+
+impl SlideTimer {
+    pub const fn new() -> Self {
+        Self {
+            seconds: 0,
+            tick: None,
+        }
+    }
+
+    pub fn start(&mut self) {
+        if self.tick.is_none() {
+            self.tick = Some(Instant::now());
+        }
+    }
+
+    pub fn stop(&mut self) {
+        self.poll();
+        self.tick = None;
+    }
+
+    pub fn reset(&mut self) {
+        self.seconds = 0;
+        if self.tick.is_some() {
+            self.tick = Some(Instant::now());
+        }
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.tick.is_some()
+    }
+
+    pub fn seconds(&self) -> u32 {
+        self.seconds
+    }
+
+    /// Counts the seconds passed, which must be called well within the wrap
+    /// around of `Instant`
+    pub fn poll(&mut self) {
+        if let Some(tick) = &mut self.tick {
+            while tick.elapsed_ms() >= 1000 {
+                self.seconds = self.seconds.saturating_add(1);
+                *tick = tick.add_ms(1000);
+            }
+        }
+    }
+}
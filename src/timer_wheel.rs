@@ -0,0 +1,120 @@
+//! Software timers on a hashed timing wheel
+//!
+//! A timer due at millisecond `t` is kept in the list of slot `t % SLOTS`.
+//! `expire` moves a cursor over the milliseconds up to now and only looks at
+//! the slot of each one, so it doesn't go through every pending timer on every
+//! call. Timers more than `SLOTS` ms away stay in their slot for more turns.
+
+/// Slots of the wheel, one per millisecond
+const SLOTS: usize = 32;
+
+#[derive(Clone, Copy, Debug)]
+struct Entry<T> {
+    deadline: u32,
+    timer: T,
+    /// Next entry in the same slot
+    next: Option<usize>,
+}
+
+/// `N` timers of type `T`, on the millisecond clock of `clock::millis`
+#[derive(Debug)]
+pub struct TimerWheel<T, const N: usize> {
+    entries: [Option<Entry<T>>; N],
+    /// First entry of each slot
+    slots: [Option<usize>; SLOTS],
+    /// Next millisecond to look at
+    cursor: u32,
+}
+
+impl<T: Copy, const N: usize> TimerWheel<T, N> {
+    pub fn new() -> Self {
+        Self {
+            entries: [None; N],
+            slots: [None; SLOTS],
+            cursor: 0,
+        }
+    }
+
+    /// Starts `timer`, due `delay_ms` after `now`, giving it back if all `N`
+    /// timers are taken
+    pub fn schedule(&mut self, now: u32, delay_ms: u32, timer: T) -> Result<(), T> {
+        let idx = match self.entries.iter().position(Option::is_none) {
+            Some(idx) => idx,
+            None => return Err(timer),
+        };
+        let mut deadline = now.wrapping_add(delay_ms);
+        // The cursor won't come back to a slot it has passed for a whole turn
+        if is_before(deadline, self.cursor) {
+            deadline = self.cursor;
+        }
+        let slot = deadline as usize % SLOTS;
+        self.entries[idx] = Some(Entry {
+            deadline,
+            timer,
+            next: self.slots[slot],
+        });
+        self.slots[slot] = Some(idx);
+        Ok(())
+    }
+
+    /// Stops the timers for which `f` returns true
+    pub fn cancel(&mut self, f: impl Fn(&T) -> bool) {
+        for slot in 0..SLOTS {
+            let mut prev = None;
+            let mut cur = self.slots[slot];
+            while let Some(idx) = cur {
+                let entry = self.entries[idx].unwrap();
+                cur = entry.next;
+                if f(&entry.timer) {
+                    self.unlink(slot, prev, idx);
+                } else {
+                    prev = Some(idx);
+                }
+            }
+        }
+    }
+
+    /// Takes the next timer due by `now`, one at a time
+    pub fn expire(&mut self, now: u32) -> Option<T> {
+        // Every slot gets looked at in the last turn, so older ones can be
+        // skipped after a long gap
+        let oldest = now.wrapping_sub(SLOTS as u32 - 1);
+        if is_before(self.cursor, oldest) {
+            self.cursor = oldest;
+        }
+
+        while !is_before(now, self.cursor) {
+            let slot = self.cursor as usize % SLOTS;
+            let mut prev = None;
+            let mut cur = self.slots[slot];
+            while let Some(idx) = cur {
+                let entry = self.entries[idx].unwrap();
+                if !is_before(self.cursor, entry.deadline) {
+                    self.unlink(slot, prev, idx);
+                    return Some(entry.timer);
+                }
+                prev = Some(idx);
+                cur = entry.next;
+            }
+            self.cursor = self.cursor.wrapping_add(1);
+        }
+        None
+    }
+
+    fn unlink(&mut self, slot: usize, prev: Option<usize>, idx: usize) {
+        let next = self.entries[idx].take().and_then(|entry| entry.next);
+        match prev {
+            Some(prev) => {
+                if let Some(entry) = &mut self.entries[prev] {
+                    entry.next = next;
+                }
+            }
+            None => self.slots[slot] = next,
+        }
+    }
+}
+
+/// Whether millisecond `a` comes before `b`, across the wrap around
+fn is_before(a: u32, b: u32) -> bool {
+    (a.wrapping_sub(b) as i32) < 0
+}
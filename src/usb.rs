@@ -0,0 +1,114 @@
+//! HID interfaces of the receiver
+//!
+//! They are an RTIC resource of the `OTG_FS` task, which polls them, and of
+//! the tasks running commands, which lock them to push reports.
+
+use crate::hid_report::*;
+use stm32l4xx_hal::otg_fs::{UsbBus, USB};
+use usb_device::UsbError;
+use usbd_hid::hid_class::HIDClass;
+
+pub type UsbBusType = UsbBus<USB>;
+
+/// One `HIDClass` per interface
+///
+/// The OTG_FS peripheral has 6 IN endpoints: control, two for the serial
+/// port and one per class here. That leaves too few for a class per report,
+/// so `pointer` carries the mouse and the cursor and `ctrl` the consumer and
+/// system controls, told apart by report IDs.
+pub struct HidClasses {
+    pub pointer: HIDClass<'static, UsbBusType>,
+    pub kbd: HIDClass<'static, UsbBusType>,
+    pub ctrl: HIDClass<'static, UsbBusType>,
+}
+
+impl HidClasses {
+    pub fn send_cursor_report(&mut self, x: u16, y: u16) -> Result<(), UsbError> {
+        let report = CursorReport::with_position(x, y);
+
+        debug!("Send report: {:?}", &report);
+
+        let result = self.pointer.push_raw_input(&report.to_bytes()).map(|_| ());
+        if let Err(e) = result {
+            error!("Cursor Report Error: {:?}", e);
+        }
+        result
+    }
+
+    pub fn send_mouse_report(&mut self, x: i16, y: i16, btn: MouseButtons) -> Result<(), UsbError> {
+        let report = MouseReport {
+            buttons: btn.bits(),
+            x,
+            y,
+            wheel: 0,
+        };
+
+        debug!("Send report: {:?}", &report);
+
+        let result = self.pointer.push_raw_input(&report.to_bytes()).map(|_| ());
+        if let Err(e) = result {
+            error!("Mouse Report Error: {:?}", e);
+        }
+        result
+    }
+
+    pub fn send_wheel_report(&mut self, wheel: i8, btn: MouseButtons) -> Result<(), UsbError> {
+        let report = MouseReport {
+            buttons: btn.bits(),
+            wheel,
+            ..MouseReport::default()
+        };
+
+        debug!("Send report: {:?}", &report);
+
+        let result = self.pointer.push_raw_input(&report.to_bytes()).map(|_| ());
+        if let Err(e) = result {
+            error!("Wheel Report Error: {:?}", e);
+        }
+        result
+    }
+
+    pub fn send_kbd_report(
+        &mut self,
+        modifiers: KeyboardModifiers,
+        keys: &[u8; 6],
+    ) -> Result<(), UsbError> {
+        let report = KeyboardReport {
+            modifier: modifiers.bits(),
+            keycodes: *keys,
+            leds: 0,
+        };
+
+        debug!("Send report: {:?}", &report);
+
+        let result = self.kbd.push_input(&report).map(|_| ());
+        if let Err(e) = result {
+            error!("Keyboard Report Error: {:?}", e);
+        }
+        result
+    }
+
+    pub fn send_consumer_report(&mut self, usage_id: u16) -> Result<(), UsbError> {
+        let report = ConsumerReport { usage_id };
+
+        debug!("Send report: {:?}", &report);
+
+        let result = self.ctrl.push_raw_input(&report.to_bytes()).map(|_| ());
+        if let Err(e) = result {
+            error!("Consumer Report Error: {:?}", e);
+        }
+        result
+    }
+
+    pub fn send_system_report(&mut self, usage_id: u8) -> Result<(), UsbError> {
+        let report = SystemReport { usage_id };
+
+        debug!("Send report: {:?}", &report);
+
+        let result = self.ctrl.push_raw_input(&report.to_bytes()).map(|_| ());
+        if let Err(e) = result {
+            error!("System Report Error: {:?}", e);
+        }
+        result
+    }
+}
@@ -1,8 +1,8 @@
-use crate::USB_SER;
-use core::fmt::{self, Write};
-use cortex_m::{asm, interrupt::free};
+use crate::serial::SerialWriter;
+use core::fmt::Write;
+use cortex_m::peripheral::NVIC;
 use log::{LevelFilter, Log, Metadata, Record};
-use usb_device::UsbError;
+use stm32l4xx_hal::stm32::Interrupt;
 
 #[derive(Clone, Debug)]
 pub struct UsbLogger;
@@ -14,33 +14,6 @@ impl UsbLogger {
     }
 }
 
-impl Write for UsbLogger {
-    fn write_str(&mut self, s: &str) -> fmt::Result {
-        free(|cs| {
-            let mut usb_ser_ref = USB_SER
-                .borrow(cs)
-                .try_borrow_mut()
-                .map_err(|_| fmt::Error)?;
-            let usb_ser = usb_ser_ref.as_mut().unwrap();
-
-            for data in s.as_bytes().chunks(64) {
-                if let Err(e) = usb_ser.write(data) {
-                    if matches!(e, UsbError::WouldBlock) {
-                        return Err(fmt::Error);
-                    }
-                    while usb_ser.flush().is_err() {
-                        asm::nop();
-                    }
-                    // Retry again
-                    usb_ser.write(data).ok();
-                }
-            }
-            Ok(())
-        })?;
-        Ok(())
-    }
-}
-
 impl Log for UsbLogger {
     fn enabled(&self, _metadata: &Metadata) -> bool {
         true
@@ -48,10 +21,10 @@ impl Log for UsbLogger {
 
     fn log(&self, record: &Record) {
         if self.enabled(record.metadata()) {
-            let mut logger = self.clone();
+            let mut writer = SerialWriter;
             if let Some(module_path) = record.module_path_static() {
                 write!(
-                    &mut logger,
+                    &mut writer,
                     "[{}] {}: {}\r\n",
                     record.level(),
                     module_path,
@@ -59,17 +32,13 @@ impl Log for UsbLogger {
                 )
                 .ok();
             } else {
-                write!(&mut logger, "[{}] {}\r\n", record.level(), record.args()).ok();
+                write!(&mut writer, "[{}] {}\r\n", record.level(), record.args()).ok();
             }
         }
     }
 
     fn flush(&self) {
-        free(|cs| {
-            let mut usb_ser_ref = USB_SER.borrow(cs).borrow_mut();
-            let usb_ser = usb_ser_ref.as_mut().unwrap();
-
-            usb_ser.flush().ok();
-        });
+        // The USB task sends out what is queued
+        NVIC::pend(Interrupt::OTG_FS);
     }
 }
@@ -0,0 +1,96 @@
+use crate::clock;
+use core::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use stm32l4xx_hal::stm32::OTG_FS_DEVICE;
+use usb_device::device::UsbDeviceState;
+
+/// How long resume signalling is driven, within the 1-15 ms allowed by USB 2.0
+const REMOTE_WAKEUP_MS: u32 = 5;
+/// How long the host gets to resume after a remote wakeup, which takes it
+/// about 20 ms more than the signalling
+const RESUME_WAIT_MS: u32 = 100;
+
+static CONFIGURED: AtomicBool = AtomicBool::new(false);
+static SUSPENDED: AtomicBool = AtomicBool::new(false);
+static REMOTE_WAKEUP_ENABLED: AtomicBool = AtomicBool::new(false);
+/// Resume is being signalled, since `WAKEUP_STARTED` in `clock::millis`
+static WAKING_UP: AtomicBool = AtomicBool::new(false);
+static WAKEUP_STARTED: AtomicU32 = AtomicU32::new(0);
+/// A remote wakeup was sent and the host has not resumed yet
+static RESUMING: AtomicBool = AtomicBool::new(false);
+
+/// Records the device state after `UsbDevice::poll`
+pub fn update(state: UsbDeviceState, remote_wakeup_enabled: bool) {
+    let suspended = state == UsbDeviceState::Suspend;
+    if SUSPENDED.swap(suspended, Ordering::Relaxed) != suspended {
+        if suspended {
+            info!("USB suspended");
+        } else {
+            info!("USB resumed");
+        }
+    }
+    if !suspended {
+        RESUMING.store(false, Ordering::Relaxed);
+    }
+    REMOTE_WAKEUP_ENABLED.store(remote_wakeup_enabled, Ordering::Relaxed);
+    // A suspended device keeps its configuration
+    if !suspended {
+        CONFIGURED.store(state == UsbDeviceState::Configured, Ordering::Relaxed);
+    }
+}
+
+/// Whether the host has set the device up, i.e. it is connected
+pub fn is_configured() -> bool {
+    CONFIGURED.load(Ordering::Relaxed)
+}
+
+pub fn is_suspended() -> bool {
+    SUSPENDED.load(Ordering::Relaxed)
+}
+
+/// Starts signalling resume to a suspended host, returning whether it was
+/// started
+///
+/// The host only accepts this if it has enabled remote wakeup, which it
+/// does before suspending a device that supports it. `poll` ends the
+/// signalling after `REMOTE_WAKEUP_MS`.
+pub fn remote_wakeup() -> bool {
+    if !is_suspended()
+        || !REMOTE_WAKEUP_ENABLED.load(Ordering::Relaxed)
+        || WAKING_UP.load(Ordering::Relaxed)
+    {
+        return false;
+    }
+
+    info!("Sending remote wakeup");
+    let otg_device = unsafe { &(*OTG_FS_DEVICE::ptr()) };
+    otg_device.dctl.modify(|_, w| w.rwusig().set_bit());
+    WAKEUP_STARTED.store(clock::millis(), Ordering::Relaxed);
+    WAKING_UP.store(true, Ordering::Relaxed);
+    RESUMING.store(true, Ordering::Relaxed);
+    true
+}
+
+/// Whether the host is being woken up by `remote_wakeup`, so reports sent
+/// now would be lost
+///
+/// Gives up after `RESUME_WAIT_MS`, in case the host stays asleep.
+pub fn is_resuming() -> bool {
+    RESUMING.load(Ordering::Relaxed)
+        && is_suspended()
+        && clock::millis().wrapping_sub(WAKEUP_STARTED.load(Ordering::Relaxed)) < RESUME_WAIT_MS
+}
+
+/// Ends resume signalling once it has lasted long enough, which must be
+/// called every millisecond
+pub fn poll() {
+    if !WAKING_UP.load(Ordering::Relaxed) {
+        return;
+    }
+    // One more than asked for, as the start is only known to the millisecond
+    let elapsed = clock::millis().wrapping_sub(WAKEUP_STARTED.load(Ordering::Relaxed));
+    if elapsed > REMOTE_WAKEUP_MS {
+        let otg_device = unsafe { &(*OTG_FS_DEVICE::ptr()) };
+        otg_device.dctl.modify(|_, w| w.rwusig().clear_bit());
+        WAKING_UP.store(false, Ordering::Relaxed);
+    }
+}